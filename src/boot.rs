@@ -0,0 +1,109 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::model::Model;
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100; // 256 B
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900; // 2304 B
+
+#[derive(Debug)]
+pub enum BootRomError {
+    Io(io::Error),
+    InvalidSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootRomError::Io(err) => write!(f, "could not read boot ROM: {}", err),
+            BootRomError::InvalidSize(size) => write!(
+                f,
+                "boot ROM is {} bytes, expected {} (DMG) or {} (CGB)",
+                size, DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE
+            ),
+        }
+    }
+}
+
+impl error::Error for BootRomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BootRomError::Io(err) => Some(err),
+            BootRomError::InvalidSize(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for BootRomError {
+    fn from(err: io::Error) -> Self {
+        BootRomError::Io(err)
+    }
+}
+
+// a boot ROM image supplied by the user. the model it belongs to is worked out from its size.
+#[derive(Debug, Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+    model: Model,
+}
+
+impl BootRom {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BootRomError> {
+        let model = match data.len() {
+            DMG_BOOT_ROM_SIZE => Model::Dmg,
+            CGB_BOOT_ROM_SIZE => Model::Cgb,
+            size => return Err(BootRomError::InvalidSize(size)),
+        };
+
+        Ok(BootRom {
+            data: data.to_vec(),
+            model,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, BootRomError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // the byte the overlay puts at addr, or None where the cartridge shows through. the CGB
+    // image skips 0x0100 - 0x01ff so the cartridge header stays visible.
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match (self.model, addr) {
+            (_, 0x0000..=0x00ff) => Some(self.data[addr as usize]),
+            (Model::Cgb, 0x0200..=0x08ff) => Some(self.data[addr as usize]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_from_size() {
+        let dmg = BootRom::from_bytes(&[0; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(dmg.model(), Model::Dmg);
+        assert_eq!(dmg.read(0x0200), None);
+
+        let cgb = BootRom::from_bytes(&[0; CGB_BOOT_ROM_SIZE]).unwrap();
+        assert_eq!(cgb.model(), Model::Cgb);
+        assert_eq!(cgb.read(0x0150), None);
+        assert_eq!(cgb.read(0x0200), Some(0));
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        match BootRom::from_bytes(&[0; 0x200]) {
+            Err(BootRomError::InvalidSize(0x200)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::interrupt::{self, Interrupt};
+use crate::joypad::P1_ADDR;
+use crate::model::KEY1_ADDR;
+
+// the address bus as the CPU sees it. devices share it to talk to each other, so anything that
+// raises an interrupt does it through here too.
+pub trait Bus {
+    fn read_byte(&self, addr: u16) -> u8;
+
+    fn write_byte(&mut self, addr: u16, data: u8);
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flag = self.read_byte(interrupt::IF_ADDR);
+        self.write_byte(interrupt::IF_ADDR, flag | interrupt.mask());
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        let flag = self.read_byte(interrupt::IF_ADDR);
+        self.write_byte(interrupt::IF_ADDR, flag & !interrupt.mask());
+    }
+
+    // interrupts that are both requested and enabled.
+    fn pending_interrupts(&self) -> u8 {
+        self.read_byte(interrupt::IF_ADDR) & self.read_byte(interrupt::IE_ADDR) & 0x1f
+    }
+
+    // true while a selected joypad line reads low, which is what brings the CPU out of STOP.
+    fn joypad_line_low(&self) -> bool {
+        self.read_byte(P1_ADDR) & 0x0f != 0x0f
+    }
+
+    // performs an armed CGB speed switch. bit 7 of KEY1 shows the new speed and the armed bit in
+    // bit 0 clears.
+    fn switch_speed(&mut self) {
+        let key1 = self.read_byte(KEY1_ADDR);
+        self.write_byte(KEY1_ADDR, (key1 ^ 0x80) & 0xfe);
+    }
+
+    // advances the devices on the bus by one M-cycle. the CPU calls this for every M-cycle it runs,
+    // straight after the memory access if the cycle has one. a bus without clocked devices has
+    // nothing to do.
+    fn tick(&mut self) {}
+}
+
+// 64K of plain RAM with no memory map, handy for running the CPU on its own.
+pub struct FlatBus {
+    pub memory: Box<[u8; 0x10000]>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            memory: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
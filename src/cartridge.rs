@@ -0,0 +1,584 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use rtc::{Rtc, RtcSync, RTC_SAVE_SIZE};
+
+const HEADER_END: usize = 0x0150;
+const TITLE_ADDR: usize = 0x0134;
+const MANUFACTURER_ADDR: usize = 0x013f;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const NEW_LICENSEE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
+const TYPE_ADDR: usize = 0x0147;
+const ROM_SIZE_ADDR: usize = 0x0148;
+const RAM_SIZE_ADDR: usize = 0x0149;
+const OLD_LICENSEE_ADDR: usize = 0x014b;
+const VERSION_ADDR: usize = 0x014c;
+const HEADER_CHECKSUM_ADDR: usize = 0x014d;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x014e;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    TooSmall(usize),
+    HeaderChecksum { expected: u8, actual: u8 },
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    SizeMismatch { header: usize, actual: usize },
+    UnsupportedType(u8),
+    SaveSize { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "I/O error: {}", err),
+            CartridgeError::TooSmall(size) => {
+                write!(f, "ROM is {} bytes, too small to hold a header", size)
+            }
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum is {:#04x}, header says {:#04x}",
+                actual, expected
+            ),
+            CartridgeError::InvalidRomSize(code) => write!(f, "unknown ROM size {:#04x}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "unknown RAM size {:#04x}", code),
+            CartridgeError::SizeMismatch { header, actual } => {
+                write!(f, "ROM is {} bytes, header says {} bytes", actual, header)
+            }
+            CartridgeError::UnsupportedType(code) => {
+                write!(f, "unsupported cartridge type {:#04x}", code)
+            }
+            CartridgeError::SaveSize { expected, actual } => {
+                write!(f, "save is {} bytes, expected {} bytes", actual, expected)
+            }
+        }
+    }
+}
+
+impl error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CartridgeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    Unsupported,
+    Compatible, // runs on both DMG and CGB
+    Required,   // CGB only
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String), // two ASCII characters, used when the old code is 0x33
+}
+
+// the bank controller a cartridge type is built around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb = match rom[CGB_FLAG_ADDR] {
+            0xc0 => CgbSupport::Required,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::Unsupported,
+        };
+
+        // later carts shortened the title to make room for a manufacturer code and the CGB flag.
+        let code = &rom[MANUFACTURER_ADDR..CGB_FLAG_ADDR];
+        let manufacturer = if cgb != CgbSupport::Unsupported
+            && code
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            Some(String::from_utf8_lossy(code).into_owned())
+        } else {
+            None
+        };
+        let title_end = match (&manufacturer, cgb) {
+            (Some(_), _) => MANUFACTURER_ADDR,
+            (None, CgbSupport::Unsupported) => NEW_LICENSEE_ADDR,
+            (None, _) => CGB_FLAG_ADDR,
+        };
+        let title = rom[TITLE_ADDR..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+
+        let licensee = match rom[OLD_LICENSEE_ADDR] {
+            0x33 => Licensee::New(
+                String::from_utf8_lossy(&rom[NEW_LICENSEE_ADDR..SGB_FLAG_ADDR]).into_owned(),
+            ),
+            code => Licensee::Old(code),
+        };
+
+        let rom_size = match rom[ROM_SIZE_ADDR] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::InvalidRomSize(code)),
+        };
+        let ram_size = match rom[RAM_SIZE_ADDR] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::InvalidRamSize(code)),
+        };
+
+        Ok(Header {
+            title,
+            manufacturer,
+            cgb,
+            sgb: rom[SGB_FLAG_ADDR] == 0x03,
+            cartridge_type: rom[TYPE_ADDR],
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[VERSION_ADDR],
+            header_checksum: rom[HEADER_CHECKSUM_ADDR],
+            global_checksum: ((rom[GLOBAL_CHECKSUM_ADDR] as u16) << 8)
+                | rom[GLOBAL_CHECKSUM_ADDR + 1] as u16,
+        })
+    }
+
+    pub fn controller(&self) -> Option<Controller> {
+        match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => Some(Controller::RomOnly),
+            0x01..=0x03 => Some(Controller::Mbc1),
+            0x05 | 0x06 => Some(Controller::Mbc2),
+            0x0f..=0x13 => Some(Controller::Mbc3),
+            0x19..=0x1e => Some(Controller::Mbc5),
+            _ => None,
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff
+        )
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0f | 0x10)
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.cartridge_type, 0x1c..=0x1e)
+    }
+}
+
+// the boot ROM refuses to start a cartridge when this doesn't match the header.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+// nothing on real hardware checks this one.
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM_ADDR && i != GLOBAL_CHECKSUM_ADDR + 1)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+// where addr lands in a ROM of rom_len bytes with bank mapped at it. banks past the end of the
+// ROM wrap around, the controllers ignore the address lines it doesn't have.
+fn rom_offset(rom_len: usize, bank: usize, addr: u16) -> usize {
+    let banks = rom_len / ROM_BANK_SIZE;
+    (bank % banks) * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))
+}
+
+// where addr in 0xa000 - 0xbfff lands in a RAM of ram_len bytes with bank mapped at it, if there
+// is any RAM. RAM smaller than the bank mirrors across it.
+fn ram_offset(ram_len: usize, bank: usize, addr: u16) -> Option<usize> {
+    if ram_len == 0 {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (addr - 0xa000) as usize) % ram_len)
+}
+
+// maps the cartridge into 0x0000 - 0x7fff and 0xa000 - 0xbfff. writes to the ROM area go to
+// the bank controller's registers.
+pub trait Mbc {
+    fn read_rom(&self, addr: u16) -> u8;
+
+    fn write_rom(&mut self, addr: u16, data: u8);
+
+    fn read_ram(&self, addr: u16) -> u8;
+
+    // returns true if the write was stored, false if the controller ignored it.
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool;
+
+    // external RAM as it's laid out in a save file.
+    fn ram(&self) -> &[u8];
+
+    fn load_ram(&mut self, data: &[u8]);
+
+    // advances anything on the cartridge that keeps time by clocks of the 4 MiHz system clock.
+    fn tick(&mut self, _clocks: u32) {}
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    // motor transitions since the last call, for carts with a rumble motor.
+    fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        Vec::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RumbleEvent {
+    MotorOn,
+    MotorOff,
+}
+
+// 32 KiB of ROM with no banking, optionally with up to 8 KiB of RAM.
+struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom.get(addr as usize).copied().unwrap_or(0xff)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram
+            .get((addr - 0xa000) as usize)
+            .copied()
+            .unwrap_or(0xff)
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        match self.ram.get_mut((addr - 0xa000) as usize) {
+            Some(b) => {
+                *b = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        self.ram.copy_from_slice(data);
+    }
+}
+
+pub struct Cartridge {
+    header: Header,
+    global_checksum_valid: bool, // not fatal, plenty of homebrew gets it wrong
+    mbc: Box<dyn Mbc>,
+    ram_dirty: bool, // RAM was written since the last save
+}
+
+impl fmt::Debug for Cartridge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cartridge")
+            .field("header", &self.header)
+            .finish()
+    }
+}
+
+impl Cartridge {
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = Header::parse(&rom)?;
+
+        let actual = header_checksum(&rom);
+        if actual != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: header.header_checksum,
+                actual,
+            });
+        }
+        if rom.len() != header.rom_size {
+            return Err(CartridgeError::SizeMismatch {
+                header: header.rom_size,
+                actual: rom.len(),
+            });
+        }
+
+        let global_checksum_valid = global_checksum(&rom) == header.global_checksum;
+        let ram = vec![0; header.ram_size];
+        let mbc: Box<dyn Mbc> = match header.controller() {
+            Some(Controller::RomOnly) => Box::new(RomOnly { rom, ram }),
+            Some(Controller::Mbc1) => Box::new(mbc1::Mbc1::new(rom, ram)),
+            Some(Controller::Mbc2) => Box::new(mbc2::Mbc2::new(rom)),
+            Some(Controller::Mbc3) => {
+                let rtc = if header.has_rtc() {
+                    Some(Rtc::new())
+                } else {
+                    None
+                };
+                Box::new(mbc3::Mbc3::new(rom, ram, rtc))
+            }
+            Some(Controller::Mbc5) => Box::new(mbc5::Mbc5::new(rom, ram, header.has_rumble())),
+            _ => return Err(CartridgeError::UnsupportedType(header.cartridge_type)),
+        };
+
+        Ok(Cartridge {
+            header,
+            global_checksum_valid,
+            mbc,
+            ram_dirty: false,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery()
+    }
+
+    // true once RAM has been written since the save data was last taken.
+    pub fn save_pending(&self) -> bool {
+        self.ram_dirty
+    }
+
+    // battery backed RAM, followed on clock carts by the 48 byte trailer other emulators use.
+    pub fn save_data(&mut self) -> Vec<u8> {
+        self.ram_dirty = false;
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(rtc) = self.mbc.rtc() {
+            data.extend_from_slice(&rtc.save());
+        }
+        data
+    }
+
+    // accepts saves with or without the clock trailer, a missing clock just keeps running from
+    // where it is.
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let ram_size = self.mbc.ram().len();
+        let has_rtc = self.mbc.rtc().is_some();
+        if data.len() != ram_size && !(has_rtc && data.len() == ram_size + RTC_SAVE_SIZE) {
+            return Err(CartridgeError::SaveSize {
+                expected: ram_size + if has_rtc { RTC_SAVE_SIZE } else { 0 },
+                actual: data.len(),
+            });
+        }
+
+        let (ram, trailer) = data.split_at(ram_size);
+        self.mbc.load_ram(ram);
+        if let (Some(rtc), Ok(trailer)) = (self.mbc.rtc(), trailer.try_into()) {
+            rtc.load(trailer);
+        }
+        self.ram_dirty = false;
+        Ok(())
+    }
+
+    pub fn load_save_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CartridgeError> {
+        self.load_save_data(&fs::read(path)?)
+    }
+
+    // writes next to the old save and renames over it, so a crash mid-write doesn't lose it.
+    pub fn write_save_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), CartridgeError> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("sav.tmp");
+        fs::write(&tmp, self.save_data())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn set_rtc_sync(&mut self, sync: RtcSync) {
+        if let Some(rtc) = self.mbc.rtc() {
+            rtc.set_sync(sync);
+        }
+    }
+
+    pub fn tick(&mut self, clocks: u32) {
+        self.mbc.tick(clocks);
+    }
+
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        self.mbc.take_rumble_events()
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mbc.read_rom(addr)
+    }
+
+    pub fn write_rom(&mut self, addr: u16, data: u8) {
+        self.mbc.write_rom(addr, data);
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mbc.read_ram(addr)
+    }
+
+    pub fn write_ram(&mut self, addr: u16, data: u8) {
+        if self.mbc.write_ram(addr, data) {
+            self.ram_dirty = true;
+        }
+    }
+}
+
+// builds a ROM image with a valid header for tests.
+#[cfg(test)]
+pub(crate) fn test_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000 << rom_size];
+    rom[TITLE_ADDR..TITLE_ADDR + 4].copy_from_slice(b"TEST");
+    rom[TYPE_ADDR] = cartridge_type;
+    rom[ROM_SIZE_ADDR] = rom_size;
+    rom[RAM_SIZE_ADDR] = ram_size;
+    rom[HEADER_CHECKSUM_ADDR] = header_checksum(&rom);
+    let global = global_checksum(&rom);
+    rom[GLOBAL_CHECKSUM_ADDR] = (global >> 8) as u8;
+    rom[GLOBAL_CHECKSUM_ADDR + 1] = global as u8;
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let mut rom = test_rom(0x09, 0x00, 0x02);
+        rom[TITLE_ADDR..TITLE_ADDR + 15].copy_from_slice(b"POKEMON YELLAPS");
+        rom[CGB_FLAG_ADDR] = 0x80;
+        rom[OLD_LICENSEE_ADDR] = 0x33;
+        rom[NEW_LICENSEE_ADDR..NEW_LICENSEE_ADDR + 2].copy_from_slice(b"01");
+        rom[SGB_FLAG_ADDR] = 0x03;
+
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON YEL");
+        assert_eq!(header.manufacturer.as_deref(), Some("LAPS"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert!(header.sgb);
+        assert_eq!(header.licensee, Licensee::New("01".to_string()));
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0x2000);
+        assert_eq!(header.controller(), Some(Controller::RomOnly));
+        assert!(header.has_battery());
+    }
+
+    #[test]
+    fn test_checksums() {
+        let rom = test_rom(0x00, 0x00, 0x00);
+        let cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        assert_eq!(cartridge.header().title, "TEST");
+        assert!(cartridge.global_checksum_valid());
+
+        let mut bad = rom;
+        bad[TITLE_ADDR] = b'B';
+        match Cartridge::from_bytes(bad) {
+            Err(CartridgeError::HeaderChecksum { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom.truncate(0x4000);
+        match Cartridge::from_bytes(rom) {
+            Err(CartridgeError::SizeMismatch { header, actual }) => {
+                assert_eq!((header, actual), (0x8000, 0x4000));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let rom = test_rom(0x10, 0x00, 0x03);
+        let mut cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        // writes with RAM disabled are dropped and leave nothing to save.
+        cartridge.write_ram(0xa000, 0x42);
+        assert!(!cartridge.save_pending());
+
+        cartridge.write_rom(0x0000, 0x0a);
+        cartridge.write_rom(0x4000, 0x02);
+        cartridge.write_ram(0xa123, 0x42);
+        assert!(cartridge.save_pending());
+
+        let data = cartridge.save_data();
+        assert!(!cartridge.save_pending());
+        assert_eq!(data.len(), 0x8000 + RTC_SAVE_SIZE);
+
+        let mut restored = Cartridge::from_bytes(rom).unwrap();
+        restored.load_save_data(&data).unwrap();
+        restored.write_rom(0x0000, 0x0a);
+        restored.write_rom(0x4000, 0x02);
+        assert_eq!(restored.read_ram(0xa123), 0x42);
+
+        // saves from emulators that don't store the clock still load.
+        restored.load_save_data(&data[..0x8000]).unwrap();
+        match restored.load_save_data(&data[..0x100]) {
+            Err(CartridgeError::SaveSize { expected, actual }) => {
+                assert_eq!((expected, actual), (0x8000 + RTC_SAVE_SIZE, 0x100));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
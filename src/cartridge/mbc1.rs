@@ -0,0 +1,189 @@
+use super::{ram_offset, rom_offset, Mbc, ROM_BANK_SIZE};
+
+const LOGO_ADDR: usize = 0x0104;
+const LOGO_SIZE: usize = 0x30;
+
+// up to 2 MiB of ROM and 32 KiB of RAM. BANK1 picks the 0x4000 - 0x7fff ROM bank, BANK2 either
+// extends it or, in mode 1, picks the RAM bank and the bank at 0x0000 - 0x3fff. multicarts
+// (MBC1M) wire BANK2 one bit lower, so each game sees 16 banks.
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    bank1: u8, // 5 bits, never 0
+    bank2: u8, // 2 bits
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>) -> Self {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram,
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        ram_offset(self.ram.len(), bank, addr)
+    }
+}
+
+// a 1 MiB MBC1 cart with a second copy of the Nintendo logo at the start of bank 0x10 holds
+// several games, each starting on a 16 bank boundary.
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+    let logo = &rom[LOGO_ADDR..LOGO_ADDR + LOGO_SIZE];
+    let second = 0x10 * ROM_BANK_SIZE + LOGO_ADDR;
+    &rom[second..second + LOGO_SIZE] == logo
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff if self.mode => (self.bank2 << self.bank2_shift()) as usize,
+            0x0000..=0x3fff => 0,
+            _ => {
+                let bank1 = if self.multicart {
+                    self.bank1 & 0x0f
+                } else {
+                    self.bank1
+                };
+                ((self.bank2 << self.bank2_shift()) | bank1) as usize
+            }
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = data & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                // bank 0 can't be picked here, so 0x00, 0x20, 0x40 and 0x60 map to the bank after.
+                self.bank1 = data & 0x1f;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5fff => self.bank2 = data & 0x03,
+            _ => self.mode = data & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        match self.ram_offset(addr) {
+            Some(offset) => {
+                self.ram[offset] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        self.ram.copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::RAM_BANK_SIZE;
+
+    // every bank starts with its own bank number.
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = Mbc1::new(banked_rom(128), vec![]);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+
+        // bank 0 remaps to 1, and so do the other banks with a zero BANK1.
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+
+        // out of range banks wrap around the ROM size.
+        mbc.write_rom(0x2000, 0x1f);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x7f);
+    }
+
+    #[test]
+    fn test_mode_1_banks_low_rom_and_ram() {
+        let mut mbc = Mbc1::new(banked_rom(128), vec![0; 0x8000]);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+
+        mbc.write_ram(0xa000, 0x42);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0xa000, 0x42);
+        assert_eq!(mbc.ram[2 * RAM_BANK_SIZE], 0x42);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0xa000), 0x00);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut rom = banked_rom(64);
+        rom[LOGO_ADDR..LOGO_ADDR + LOGO_SIZE].copy_from_slice(&[0xce; LOGO_SIZE]);
+        let second = 0x10 * ROM_BANK_SIZE + LOGO_ADDR;
+        rom[second..second + LOGO_SIZE].copy_from_slice(&[0xce; LOGO_SIZE]);
+
+        let mut mbc = Mbc1::new(rom, vec![]);
+        assert!(mbc.multicart);
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+    }
+}
@@ -0,0 +1,110 @@
+use super::{rom_offset, Mbc};
+
+const RAM_SIZE: usize = 0x200;
+
+// up to 256 KiB of ROM with 512 half-bytes of RAM built into the controller. bit 8 of the
+// address decides whether a write to 0x0000 - 0x3fff enables RAM or picks the ROM bank.
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>, // only the low nibble of each byte is stored
+    ram_enabled: bool,
+    rom_bank: u8, // 4 bits, never 0
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x3fff if addr & 0x0100 == 0 => self.ram_enabled = data & 0x0f == 0x0a,
+            0x0000..=0x3fff => {
+                self.rom_bank = data & 0x0f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        // the upper nibble isn't connected and reads as 1s. 512 bytes mirror across the area.
+        0xf0 | self.ram[addr as usize & (RAM_SIZE - 1)]
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        if self.ram_enabled {
+            self.ram[addr as usize & (RAM_SIZE - 1)] = data & 0x0f;
+        }
+        self.ram_enabled
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        for (b, &d) in self.ram.iter_mut().zip(data) {
+            *b = d & 0x0f;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::ROM_BANK_SIZE;
+
+    #[test]
+    fn test_register_select_by_address_bit_8() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        rom[3 * ROM_BANK_SIZE] = 0x03;
+        let mut mbc = Mbc2::new(rom);
+
+        // bit 8 clear only touches RAM enable.
+        mbc.write_rom(0x2000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+        assert!(!mbc.ram_enabled);
+
+        mbc.write_rom(0x2100, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x03);
+
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.rom_bank, 1);
+        mbc.write_rom(0x0000, 0x0a);
+        assert!(mbc.ram_enabled);
+    }
+
+    #[test]
+    fn test_half_byte_ram_mirrors() {
+        let mut mbc = Mbc2::new(vec![0; 2 * ROM_BANK_SIZE]);
+        mbc.write_ram(0xa000, 0x5a);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0xa000, 0x5a);
+        assert_eq!(mbc.read_ram(0xa000), 0xfa);
+        assert_eq!(mbc.read_ram(0xa200), 0xfa);
+        assert_eq!(mbc.read_ram(0xbe00), 0xfa);
+    }
+}
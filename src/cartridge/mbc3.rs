@@ -0,0 +1,156 @@
+use super::rtc::Rtc;
+use super::{ram_offset, rom_offset, Mbc};
+
+// up to 2 MiB of ROM and 32 KiB of RAM, optionally with a real-time clock whose registers are
+// mapped into the RAM area in place of a RAM bank.
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool, // also covers the clock registers
+    rom_bank: u8,      // 7 bits, never 0
+    ram_select: u8,    // RAM bank 0x00 - 0x03 or clock register 0x08 - 0x0c
+    latch_armed: bool, // 0x00 was written, a 0x01 now latches the clock
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, rtc: Option<Rtc>) -> Self {
+        Mbc3 {
+            rom,
+            ram,
+            rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_armed: false,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        ram_offset(self.ram.len(), self.ram_select as usize, addr)
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enabled = data & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                self.rom_bank = data & 0x7f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5fff => self.ram_select = data,
+            _ => {
+                if self.latch_armed && data == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = data == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        match (self.ram_select, &self.rtc) {
+            (0x00..=0x03, _) => self.ram_offset(addr).map_or(0xff, |o| self.ram[o]),
+            (0x08..=0x0c, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match (self.ram_select, self.rtc.as_mut()) {
+            (0x00..=0x03, _) => match self.ram_offset(addr) {
+                Some(offset) => {
+                    self.ram[offset] = data;
+                    true
+                }
+                None => false,
+            },
+            (0x08..=0x0c, Some(rtc)) => {
+                rtc.write(self.ram_select, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        self.ram.copy_from_slice(data);
+    }
+
+    fn tick(&mut self, clocks: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(clocks);
+        }
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn test_rom_and_ram_banking() {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        rom[0x7f * ROM_BANK_SIZE] = 0x7f;
+        let mut mbc = Mbc3::new(rom, vec![0; 4 * RAM_BANK_SIZE], None);
+
+        mbc.write_rom(0x2000, 0xff);
+        assert_eq!(mbc.read_rom(0x4000), 0x7f);
+
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xa000, 0x42);
+        assert_eq!(mbc.ram[3 * RAM_BANK_SIZE], 0x42);
+
+        // clock registers read as open bus on carts without a clock.
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn test_latch_sequence() {
+        let mut mbc = Mbc3::new(vec![0; 2 * ROM_BANK_SIZE], vec![], Some(Rtc::new()));
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.write_ram(0xa000, 30);
+
+        mbc.tick(4194304);
+        assert_eq!(mbc.read_ram(0xa000) & 0x3f, 30);
+
+        // 0x01 on its own doesn't latch.
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000) & 0x3f, 30);
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000) & 0x3f, 31);
+    }
+}
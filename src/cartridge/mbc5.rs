@@ -0,0 +1,156 @@
+use super::{ram_offset, rom_offset, Mbc, RumbleEvent};
+
+// up to 8 MiB of ROM and 128 KiB of RAM. unlike the earlier controllers bank 0 can be mapped
+// into 0x4000 - 0x7fff. on rumble carts bit 3 of the RAM bank register drives the motor instead.
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rumble: bool, // cart has a motor
+    ram_enabled: bool,
+    rom_bank: u16, // 9 bits
+    ram_bank: u8,  // 4 bits, 3 on rumble carts
+    motor: bool,
+    rumble_events: Vec<RumbleEvent>, // motor transitions not yet collected by the frontend
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram: Vec<u8>, rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram,
+            rumble,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            motor: false,
+            rumble_events: Vec::new(),
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        ram_offset(self.ram.len(), self.ram_bank as usize, addr)
+    }
+
+    fn set_motor(&mut self, on: bool) {
+        if on != self.motor {
+            self.motor = on;
+            self.rumble_events.push(if on {
+                RumbleEvent::MotorOn
+            } else {
+                RumbleEvent::MotorOff
+            });
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x0000..=0x3fff => 0,
+            _ => self.rom_bank as usize,
+        };
+        self.rom[rom_offset(self.rom.len(), bank, addr)]
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // all 8 bits are checked, 0x1a doesn't enable RAM here.
+            0x0000..=0x1fff => self.ram_enabled = data == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((data as u16 & 1) << 8),
+            0x4000..=0x5fff if self.rumble => {
+                self.ram_bank = data & 0x07;
+                self.set_motor(data & 0x08 != 0);
+            }
+            0x4000..=0x5fff => self.ram_bank = data & 0x0f,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram_offset(addr).map_or(0xff, |o| self.ram[o])
+    }
+
+    fn write_ram(&mut self, addr: u16, data: u8) -> bool {
+        match self.ram_offset(addr) {
+            Some(offset) => {
+                self.ram[offset] = data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        self.ram.copy_from_slice(data);
+    }
+
+    fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        std::mem::take(&mut self.rumble_events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+    #[test]
+    fn test_rom_banking() {
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        rom[0x1ff * ROM_BANK_SIZE] = 0xff;
+        rom[0x100 * ROM_BANK_SIZE] = 0x10;
+        let mut mbc = Mbc5::new(rom, vec![], false);
+
+        mbc.write_rom(0x2000, 0xff);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0xff);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x10);
+
+        // bank 0 maps into the switchable area as is.
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), mbc.rom[0]);
+        assert_eq!(mbc.rom_bank, 0);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = Mbc5::new(
+            vec![0; 2 * ROM_BANK_SIZE],
+            vec![0; 16 * RAM_BANK_SIZE],
+            false,
+        );
+        mbc.write_rom(0x0000, 0x1a);
+        mbc.write_ram(0xa000, 0x42);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x0f);
+        mbc.write_ram(0xa000, 0x42);
+        assert_eq!(mbc.ram[15 * RAM_BANK_SIZE], 0x42);
+    }
+
+    #[test]
+    fn test_rumble_events() {
+        let mut mbc = Mbc5::new(vec![0; 2 * ROM_BANK_SIZE], vec![0; 8 * RAM_BANK_SIZE], true);
+        mbc.write_rom(0x4000, 0x0b);
+        mbc.write_rom(0x4000, 0x0a);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.ram_bank, 3);
+        assert_eq!(
+            mbc.take_rumble_events(),
+            vec![RumbleEvent::MotorOn, RumbleEvent::MotorOff]
+        );
+        assert!(mbc.take_rumble_events().is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CLOCKS_PER_SECOND: u32 = 4194304;
+pub const RTC_SAVE_SIZE: usize = 48;
+
+// what moves the clock forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcSync {
+    Emulated, // emulated clocks, so the clock stops while the emulator does
+    Host,     // the host's wall clock, so the clock keeps running between sessions
+}
+
+// the MBC3 real-time clock. the registers the CPU reads are a copy taken when it latches.
+#[derive(Debug, Clone)]
+pub struct Rtc {
+    seconds: u8, // 6 bits
+    minutes: u8, // 6 bits
+    hours: u8,   // 5 bits
+    days: u16,   // 9 bits
+    halt: bool,
+    carry: bool, // set when days overflows, stays set until cleared by a write
+    latched: [u8; 5],
+    clocks: u32, // clocks into the current second
+    sync: RtcSync,
+    synced_at: u64, // unix time of the last host sync
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            latched: [0; 5],
+            clocks: 0,
+            sync: RtcSync::Emulated,
+            synced_at: unix_time(),
+        }
+    }
+
+    pub fn set_sync(&mut self, sync: RtcSync) {
+        self.sync = sync;
+        self.synced_at = unix_time();
+    }
+
+    // advances by clocks of the 4 MiHz system clock.
+    pub fn tick(&mut self, clocks: u32) {
+        if self.sync != RtcSync::Emulated || self.halt {
+            return;
+        }
+        self.clocks += clocks;
+        if self.clocks >= CLOCKS_PER_SECOND {
+            let seconds = self.clocks / CLOCKS_PER_SECOND;
+            self.clocks %= CLOCKS_PER_SECOND;
+            self.advance(seconds as u64);
+        }
+    }
+
+    // catches up on host time that passed since the last sync.
+    fn sync_host(&mut self) {
+        if self.sync != RtcSync::Host {
+            return;
+        }
+        let now = unix_time();
+        if !self.halt {
+            self.advance(now.saturating_sub(self.synced_at));
+        }
+        self.synced_at = now;
+    }
+
+    pub fn latch(&mut self) {
+        self.sync_host();
+        self.latched = self.registers();
+    }
+
+    // register 0x08 - 0x0c as selected through 0x4000 - 0x5fff.
+    pub fn read(&self, register: u8) -> u8 {
+        let mask = [0x3f, 0x3f, 0x1f, 0xff, 0xc1][(register - 0x08) as usize];
+        // unused bits read as 1.
+        self.latched[(register - 0x08) as usize] | !mask
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        self.sync_host();
+        match register {
+            0x08 => {
+                self.seconds = data & 0x3f;
+                self.clocks = 0;
+            }
+            0x09 => self.minutes = data & 0x3f,
+            0x0a => self.hours = data & 0x1f,
+            0x0b => self.days = (self.days & 0x100) | data as u16,
+            _ => {
+                self.days = (self.days & 0xff) | ((data as u16 & 0x01) << 8);
+                self.halt = data & 0x40 != 0;
+                self.carry = data & 0x80 != 0;
+            }
+        }
+        // writes show up straight away when read back.
+        self.latched[(register - 0x08) as usize] = self.registers()[(register - 0x08) as usize];
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            ((self.carry as u8) << 7) | ((self.halt as u8) << 6) | (self.days >> 8) as u8,
+        ]
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // out of range values count up to the limit of their bits and wrap to 0 without carrying.
+        while seconds > 0 && (self.seconds > 59 || self.minutes > 59 || self.hours > 23) {
+            self.tick_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+
+        let total = seconds
+            + self.seconds as u64
+            + 60 * (self.minutes as u64 + 60 * (self.hours as u64 + 24 * self.days as u64));
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days > 0x1ff {
+            self.carry = true;
+        }
+        self.days = (days & 0x1ff) as u16;
+    }
+
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1ff {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    // the 48 byte layout most emulators append to .sav files: the live and latched registers as
+    // little endian u32s, then the unix time they were saved at as a u64.
+    pub fn save(&mut self) -> [u8; RTC_SAVE_SIZE] {
+        self.sync_host();
+        let mut data = [0; RTC_SAVE_SIZE];
+        for (i, register) in self.registers().iter().chain(&self.latched).enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&(*register as u32).to_le_bytes());
+        }
+        data[40..48].copy_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    pub fn load(&mut self, data: &[u8; RTC_SAVE_SIZE]) {
+        let register = |i: usize| {
+            u32::from_le_bytes([
+                data[i * 4],
+                data[i * 4 + 1],
+                data[i * 4 + 2],
+                data[i * 4 + 3],
+            ]) as u8
+        };
+        for i in 0..5 {
+            self.write(0x08 + i as u8, register(i));
+            self.latched[i] = register(i + 5);
+        }
+
+        // a host synced clock keeps running while the emulator isn't.
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.synced_at = u64::from_le_bytes(timestamp);
+        self.sync_host();
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latch_and_carry() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0b, 0xff);
+        rtc.write(0x0c, 0x01);
+        rtc.write(0x0a, 23);
+        rtc.write(0x09, 59);
+        rtc.write(0x08, 59);
+
+        rtc.tick(CLOCKS_PER_SECOND);
+        // reads come from the latched copy until latched again.
+        assert_eq!(rtc.read(0x08), 0xc0 | 59);
+
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0xc0);
+        assert_eq!(rtc.read(0x0b), 0x00);
+        assert_eq!(rtc.read(0x0c), 0x80 | 0x3e);
+    }
+
+    #[test]
+    fn test_halt_and_invalid_values() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0c, 0x40);
+        rtc.tick(CLOCKS_PER_SECOND);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08) & 0x3f, 0);
+
+        rtc.write(0x0c, 0x00);
+        rtc.write(0x08, 62);
+        rtc.tick(2 * CLOCKS_PER_SECOND);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08) & 0x3f, 0);
+        assert_eq!(rtc.read(0x09) & 0x3f, 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x09, 12);
+        rtc.write(0x0b, 3);
+        let data = rtc.save();
+
+        let mut loaded = Rtc::new();
+        loaded.load(&data);
+        loaded.latch();
+        assert_eq!(loaded.read(0x09) & 0x3f, 12);
+        assert_eq!(loaded.read(0x0b), 3);
+    }
+}
@@ -5,10 +5,35 @@ const N_FLAG: u8 = 0x40; //  0b0100_0000
 const HC_FLAG: u8 = 0x20; // 0b0010_0000
 const C_FLAG: u8 = 0x10; //  0b0001_0000
 
-use crate::mmu;
+use crate::bus::Bus;
+use crate::interrupt::Interrupt;
+use crate::model::{Model, KEY1_ADDR};
+use crate::timer::DIV_ADDR;
+
+// M-cycles taken by each unprefixed opcode. conditional branches hold the not-taken count, CB
+// opcodes are counted by execute_cb and illegal opcodes are 0.
+#[rustfmt::skip]
+const OPCODE_CYCLES: [u8; 256] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0x00
+    2, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 0x10
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x20
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 0x30
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x40
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x50
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x60
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 0x70
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x80
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0x90
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xa0
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 0xb0
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4, // 0xc0
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4, // 0xd0
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4, // 0xe0
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4, // 0xf0
+];
 
 pub struct Cpu {
-    mmu: mmu::Mmu,
+    model: Model,
     pc: u16,
     sp: u16,
     a: u8,
@@ -19,12 +44,29 @@ pub struct Cpu {
     h: u8,
     l: u8,
     f: u8,
+    ime: bool,         // interrupt master enable
+    ime_pending: bool, // EI takes effect after the next instruction
+    halted: bool,
+    halt_bug: bool, // next opcode fetch does not advance PC
+    stopped: bool,
+    locked: bool,   // an illegal opcode hangs the CPU until reset
+    bus_cycles: u8, // M-cycles of the current instruction spent on memory accesses
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
         Cpu {
-            mmu: mmu::Mmu::new(),
+            model,
             pc: 0,
             sp: 0,
             a: 0,
@@ -35,348 +77,724 @@ impl Cpu {
             h: 0,
             l: 0,
             f: 0,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked: false,
+            bus_cycles: 0,
+        }
+    }
+
+    // registers as the boot ROM leaves them, for starting a cartridge at 0x0100 directly.
+    pub fn skip_boot(model: Model) -> Self {
+        let mut cpu = Self::with_model(model);
+        match model {
+            Model::Dmg => {
+                cpu.set_af(0x01b0);
+                cpu.set_bc(0x0013);
+                cpu.set_de(0x00d8);
+                cpu.set_hl(0x014d);
+            }
+            Model::Cgb => {
+                cpu.set_af(0x1180);
+                cpu.set_bc(0x0000);
+                cpu.set_de(0xff56);
+                cpu.set_hl(0x000d);
+            }
         }
+        cpu.sp = 0xfffe;
+        cpu.pc = 0x0100;
+        cpu
     }
 
-    pub fn execute(&mut self, mmu: &mut mmu::Mmu) {
-        let opcode = self.mmu.memory[self.pc as usize];
+    // executes one instruction, or services a pending interrupt, and returns the number of
+    // M-cycles it took. the bus is ticked once for each of them.
+    pub fn execute<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.bus_cycles = 0;
+        let cycles = self.execute_instruction(bus);
+        debug_assert!(self.bus_cycles <= cycles, "more accesses than M-cycles");
+        // the memory accesses ticked their own cycles, the idle ones are ticked here.
+        for _ in self.bus_cycles..cycles {
+            bus.tick();
+        }
+        cycles
+    }
+
+    fn execute_instruction<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        if self.locked {
+            return 1;
+        }
+
+        if self.stopped {
+            // only a joypad line going low brings the CPU out of STOP. the IF bit isn't used, it
+            // may be left over from an earlier press nobody serviced.
+            if !bus.joypad_line_low() {
+                return 1;
+            }
+            self.stopped = false;
+        }
+
+        if self.halted {
+            // any pending interrupt ends HALT, even with IME off, it just isn't serviced then.
+            if bus.pending_interrupts() == 0 {
+                return 1;
+            }
+            self.halted = false;
+        }
+
+        if self.ime && bus.pending_interrupts() != 0 {
+            return self.dispatch_interrupt(bus);
+        }
+
+        let ime_pending = self.ime_pending;
+        let opcode = self.fetch_byte(bus);
+        let mut cycles = OPCODE_CYCLES[opcode as usize];
         //println!("executing opcode: {:#04x}", opcode);
-        //std::thread::sleep(std::time::Duration::from_secs(20));
 
         match opcode {
-            0x31 => {
-                // load next two bytes into SP.
-                self.sp = u16_from_u8s(mmu.read_byte(self.pc + 2), mmu.read_byte(self.pc + 1));
-
-                self.pc += 3;
+            0x00 => {
+                // no operation.
             }
-            0xaf => {
-                // XOR a with itself.
-                self.a ^= self.a;
-                self.pc += 1;
+            0x02 => {
+                // load A into addr in BC.
+                self.write(bus, self.bc(), self.a);
             }
-            0x21 => {
-                // load next two bytes into HL.
-                self.h = self.mmu.read_byte(self.pc + 2);
-                self.l = self.mmu.read_byte(self.pc + 1);
-                self.pc += 3;
+            0x07 => {
+                // rotate A left, bit 7 goes to carry.
+                self.a = self.rlc(self.a);
+                self.set_z(false);
             }
-            0x32 => {
-                // decrement contents of HL and write contents of A to addr in HL.
-                let hl_data = self.hl();
-                self.set_hl(hl_data - 1);
-                self.mmu.write_byte(self.hl(), self.a);
-                self.pc += 1;
-            }
-            0xCB => {
-                let cb_code = self.mmu.read_byte(self.pc + 1);
-                self.execute_cb(cb_code);
-                self.pc += 2;
-            }
-            0x20 => {
-                // conditionally jump the pc the number of the next byte as a signed int if zero flag is not set.
-                if !self.z() {
-                    let jump = self.mmu.read_byte(self.pc + 1);
-                    self.pc += 2;
-                    self.pc = self.pc.wrapping_add((jump as i8) as u16);
-                } else {
-                    self.pc += 2;
-                }
+            0x08 => {
+                // store SP at the addr in the next two bytes.
+                let addr = self.fetch_word(bus);
+                let sp = u8s_from_16(self.sp);
+                self.write(bus, addr, sp.1);
+                self.write(bus, addr.wrapping_add(1), sp.0);
             }
-            0x0e => {
-                // load next 8 bits into C.
-                self.c = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            0x0a => {
+                // load contents of addr in BC into A.
+                self.a = self.read(bus, self.bc());
             }
-            0x3e => {
-                // load next 8 bits into A.
-                self.a = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            0x0f => {
+                // rotate A right, bit 0 goes to carry.
+                self.a = self.rrc(self.a);
+                self.set_z(false);
             }
-            0xe2 => {
-                // load a into addr 0xff00 + c.
-                self.mmu.write_byte(0xff00 + (self.c as u16), self.a);
-                self.pc += 1;
-            }
-            0x0c => {
-                // increment C.
-                let hc = eight_bit_hc(self.c, 1);
-                let result = self.c.wrapping_add(1);
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false);
-                }
-                if hc {
-                    self.set_hc(true);
+            0x10 => {
+                // STOP. the encoding is two bytes long, the second one is ignored. DIV is reset
+                // either way, on CGB an armed speed switch happens instead of stopping. both are
+                // done by the CPU itself rather than over the bus, so they take no M-cycles.
+                self.fetch_byte(bus);
+                bus.write_byte(DIV_ADDR, 0);
+                if self.model == Model::Cgb && bus.read_byte(KEY1_ADDR) & 0x01 != 0 {
+                    bus.switch_speed();
                 } else {
-                    self.set_hc(false);
+                    self.stopped = true;
                 }
-
-                self.set_n(false);
-
-                self.pc += 1;
             }
-            0x77 => {
-                // load A into memory location specified by HL.
-                self.mmu.write_byte(self.hl(), self.a);
-                self.pc += 1;
+            0x12 => {
+                // load A into addr in DE.
+                self.write(bus, self.de(), self.a);
             }
-            0xe0 => {
-                let operand = self.mmu.read_byte(self.pc + 1);
-                self.mmu.write_byte(0xff00 + (operand as u16), self.a);
-                self.pc += 2;
+            0x17 => {
+                // rotate A left through carry.
+                self.a = self.rl(self.a);
+                self.set_z(false);
             }
-            0x11 => {
-                self.set_de(u16_from_u8s(
-                    self.mmu.read_byte(self.pc + 2),
-                    self.mmu.read_byte(self.pc + 1),
-                ));
-
-                self.pc += 3;
+            0x18 => {
+                // jump relative.
+                self.jr(bus, true);
             }
             0x1a => {
                 // load contents of addr pointed to by DE into A.
-                self.a = self.mmu.read_byte(self.de());
-                self.pc += 1;
+                self.a = self.read(bus, self.de());
             }
-            0xcd => {
-                // call
-
-                let pc = u8s_from_16(self.pc);
-                self.mmu.write_byte(self.sp - 1, pc.0);
-
-                self.mmu.write_byte(self.sp - 2, pc.1);
-
-                self.sp -= 2;
-
-                self.pc += 3;
+            0x1f => {
+                // rotate A right through carry.
+                self.a = self.rr(self.a);
+                self.set_z(false);
             }
-            0x13 => {
-                // increment DE.
-                self.set_de(self.de() + 1);
-                self.pc += 1;
+            0x22 => {
+                // write contents of A to addr in HL and increment HL.
+                let hl = self.hl();
+                self.write(bus, hl, self.a);
+                self.set_hl(hl.wrapping_add(1));
             }
-            0x7b => {
-                // load E into A.
-                self.a = self.e;
-                self.pc += 1;
+            0x27 => {
+                // decimal adjust A after a BCD add or subtract.
+                self.daa();
             }
-            0xfe => {
-                // compare a with next 8 bits by subtraction.
-                let operand = self.mmu.read_byte(self.pc + 1);
-                let result = self.a.wrapping_sub(operand);
-
+            0x2a => {
+                // load contents of addr in HL into A and increment HL.
+                let hl = self.hl();
+                self.a = self.read(bus, hl);
+                self.set_hl(hl.wrapping_add(1));
+            }
+            0x2f => {
+                // complement A.
+                self.a = !self.a;
                 self.set_n(true);
-                if result == 0 {
-                    self.set_z(true);
+                self.set_hc(true);
+            }
+            0x32 => {
+                // write contents of A to addr in HL and decrement HL.
+                let hl = self.hl();
+                self.write(bus, hl, self.a);
+                self.set_hl(hl.wrapping_sub(1));
+            }
+            0x37 => {
+                // set carry flag.
+                self.set_n(false);
+                self.set_hc(false);
+                self.set_carry(true);
+            }
+            0x3a => {
+                // load contents of addr in HL into A and decrement HL.
+                let hl = self.hl();
+                self.a = self.read(bus, hl);
+                self.set_hl(hl.wrapping_sub(1));
+            }
+            0x3f => {
+                // complement carry flag.
+                self.set_n(false);
+                self.set_hc(false);
+                self.set_carry(!self.carry());
+            }
+            0x76 => {
+                // HALT until an interrupt is pending. if one already is and IME is off, the CPU
+                // doesn't halt and fails to advance PC past the next opcode instead.
+                if !self.ime && bus.pending_interrupts() != 0 {
+                    self.halt_bug = true;
                 } else {
-                    self.set_z(false);
+                    self.halted = true;
                 }
-                self.pc += 2;
             }
-            0x06 => {
-                // load next 8 bits into B.
-                self.b = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            op if op & 0xcf == 0x01 => {
+                // load next two bytes into BC, DE, HL or SP.
+                let data = self.fetch_word(bus);
+                self.write_r16(op >> 4, data);
             }
-            0x22 => {
-                // increment contents of HL and write contents of A to addr in HL.
-                let hl_data = self.hl();
-
-                self.set_hl(hl_data + 1);
-                self.mmu.write_byte(self.hl(), self.a);
-                self.pc += 1;
+            op if op & 0xcf == 0x03 => {
+                // increment BC, DE, HL or SP.
+                let data = self.read_r16(op >> 4).wrapping_add(1);
+                self.write_r16(op >> 4, data);
             }
-            0x23 => {
-                self.set_hl(self.hl() + 1);
-                self.pc += 1;
+            op if op & 0xcf == 0x09 => {
+                // add BC, DE, HL or SP to HL.
+                self.add_hl(self.read_r16(op >> 4));
             }
-            0x05 => {
-                // decrement B.
-                let result = self.b.wrapping_sub(1);
-                self.set_n(true);
-
-                self.b = result;
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false);
+            op if op & 0xcf == 0x0b => {
+                // decrement BC, DE, HL or SP.
+                let data = self.read_r16(op >> 4).wrapping_sub(1);
+                self.write_r16(op >> 4, data);
+            }
+            op if op & 0xc7 == 0x04 => {
+                // increment 8 bit register or contents of addr in HL.
+                let data = self.read_r8(bus, op >> 3);
+                let result = self.inc(data);
+                self.write_r8(bus, op >> 3, result);
+            }
+            op if op & 0xc7 == 0x05 => {
+                // decrement 8 bit register or contents of addr in HL.
+                let data = self.read_r8(bus, op >> 3);
+                let result = self.dec(data);
+                self.write_r8(bus, op >> 3, result);
+            }
+            op if op & 0xc7 == 0x06 => {
+                // load next 8 bits into register or addr in HL.
+                let data = self.fetch_byte(bus);
+                self.write_r8(bus, op >> 3, data);
+            }
+            op if op & 0xe7 == 0x20 => {
+                // conditionally jump relative on NZ, Z, NC or C.
+                let taken = self.condition(op);
+                self.jr(bus, taken);
+                if taken {
+                    cycles += 1;
                 }
-
-                self.pc += 1;
+            }
+            0x40..=0x7f => {
+                // load register into register.
+                let data = self.read_r8(bus, opcode);
+                self.write_r8(bus, opcode >> 3, data);
+            }
+            0x80..=0xbf => {
+                // 8 bit arithmetic and logic on A with a register.
+                let operand = self.read_r8(bus, opcode);
+                self.alu(opcode >> 3, operand);
+            }
+            0xc3 => {
+                // jump to addr in next two bytes.
+                self.jp(bus, true);
+            }
+            0xc9 => {
+                // return.
+                self.ret(bus, true);
+            }
+            0xcb => {
+                let cb_code = self.fetch_byte(bus);
+                cycles = self.execute_cb(bus, cb_code);
+            }
+            0xcd => {
+                // call addr in next two bytes.
+                self.call(bus, true);
+            }
+            0xd9 => {
+                // return and enable interrupts straight away.
+                self.ret(bus, true);
+                self.ime = true;
+            }
+            0xe0 => {
+                // load A into addr 0xff00 + next 8 bits.
+                let operand = self.fetch_byte(bus);
+                self.write(bus, 0xff00 | operand as u16, self.a);
+            }
+            0xe2 => {
+                // load A into addr 0xff00 + C.
+                self.write(bus, 0xff00 | self.c as u16, self.a);
+            }
+            0xe8 => {
+                // add next 8 bits as a signed int to SP.
+                self.sp = self.sp_plus_e(bus);
+            }
+            0xe9 => {
+                // jump to addr in HL.
+                self.pc = self.hl();
             }
             0xea => {
-                let addr = u16_from_u8s(
-                    self.mmu.read_byte(self.pc + 2),
-                    self.mmu.read_byte(self.pc + 1),
-                );
-
-                self.mmu.write_byte(addr, self.a);
-
-                self.pc += 3;
+                // load A into addr in next two bytes.
+                let addr = self.fetch_word(bus);
+                self.write(bus, addr, self.a);
             }
-            0x3d => {
-                // decrement A.
-                let result = self.a.wrapping_sub(1);
-                self.set_n(true);
-
-                self.a = result;
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false)
+            0xf0 => {
+                // load contents of addr 0xff00 + next 8 bits into A.
+                let addr = 0xff00 | self.fetch_byte(bus) as u16;
+                self.a = self.read(bus, addr);
+            }
+            0xf2 => {
+                // load contents of addr 0xff00 + C into A.
+                self.a = self.read(bus, 0xff00 | self.c as u16);
+            }
+            0xf3 => {
+                // disable interrupts, this also cancels an EI that has not taken effect yet.
+                self.ime = false;
+                self.ime_pending = false;
+            }
+            0xfb => {
+                // enable interrupts after the next instruction.
+                self.ime_pending = true;
+            }
+            0xf8 => {
+                // load SP plus next 8 bits as a signed int into HL.
+                let data = self.sp_plus_e(bus);
+                self.set_hl(data);
+            }
+            0xf9 => {
+                // load HL into SP.
+                self.sp = self.hl();
+            }
+            0xfa => {
+                // load contents of addr in next two bytes into A.
+                let addr = self.fetch_word(bus);
+                self.a = self.read(bus, addr);
+            }
+            op if op & 0xe7 == 0xc0 => {
+                // conditionally return on NZ, Z, NC or C.
+                let taken = self.condition(op);
+                self.ret(bus, taken);
+                if taken {
+                    cycles += 3;
                 }
-
-                self.pc += 1;
             }
-            0x28 => {
-                // conditionally jump the pc the number of the next byte as a signed int if zero flag is  set.
-                if self.z() {
-                    let jump = self.mmu.read_byte(self.pc + 1);
-                    self.pc += 2;
-                    self.pc = self.pc.wrapping_add((jump as i8) as u16);
-                } else {
-                    self.pc += 2;
+            op if op & 0xe7 == 0xc2 => {
+                // conditionally jump on NZ, Z, NC or C.
+                let taken = self.condition(op);
+                self.jp(bus, taken);
+                if taken {
+                    cycles += 1;
                 }
             }
-            0x0d => {
-                // decrement C.
-                let result = self.c.wrapping_sub(1);
-                self.set_n(true);
-
-                self.c = result;
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false)
+            op if op & 0xe7 == 0xc4 => {
+                // conditionally call on NZ, Z, NC or C.
+                let taken = self.condition(op);
+                self.call(bus, taken);
+                if taken {
+                    cycles += 3;
                 }
-
-                self.pc += 1;
             }
-            0x2e => {
-                // load next 8 bits into L.
-                self.l = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            op if op & 0xcf == 0xc1 => {
+                // pop BC, DE, HL or AF off the stack.
+                let data = self.pop(bus);
+                self.write_r16_stack(op >> 4, data);
             }
-            0x18 => {
-                // jump relative.
-                let jump = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
-                self.pc = self.pc.wrapping_add((jump as i8) as u16);
-            }
-            0x67 => {
-                // load A into H.
-                self.h = self.a;
-                self.pc += 1;
-            }
-            0x57 => {
-                // load A into D.
-                self.d = self.a;
-                self.pc += 1;
-            }
-            0x04 => {
-                // increment B.
-                let result = self.b.wrapping_add(1);
-                self.set_n(false);
-                self.set_hc(eight_bit_hc(self.b, 1));
-                self.b = result;
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false);
-                }
-
-                self.pc += 1;
+            op if op & 0xcf == 0xc5 => {
+                // push BC, DE, HL or AF onto the stack.
+                self.push(bus, self.read_r16_stack(op >> 4));
             }
-            0x1e => {
-                // load next 8 bits into E.
-                self.e = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            op if op & 0xc7 == 0xc6 => {
+                // 8 bit arithmetic and logic on A with the next 8 bits.
+                let operand = self.fetch_byte(bus);
+                self.alu(op >> 3, operand);
             }
-            0xf0 => {
-                let addr = 0xff00 | self.mmu.read_byte(self.pc + 1) as u16;
-                //println!("addr: {:#06x}", addr);
-                //println!("at addr: {:#04x}", mmu.read_byte(addr));
-                // let data = mmu.read_byte(addr);
-                self.a = mmu.read_byte(addr);
-
-                //println!("in a register: {:#04x}", self.a);
-
-                self.pc += 2;
-            }
-            // 0x1d => {
-            //     // decrement E.
-            //     let result = self.e.wrapping_sub(1);
-            //     self.set_n(true);
-
-            //     self.e = result;
-            //     if result == 0 {
-            //         self.set_z(true);
-            //     } else {
-            //         self.set_z(false)
-            //     }
-
-            //     self.pc += 1;
-            // }
-            0x24 => {
-                // increment H.
-                let hc = eight_bit_hc(self.h, 1);
-                let result = self.h.wrapping_add(1);
-                if result == 0 {
-                    self.set_z(true);
-                } else {
-                    self.set_z(false);
-                }
-                if hc {
-                    self.set_hc(true);
-                } else {
-                    self.set_hc(false);
-                }
-
-                self.set_n(false);
-
-                self.pc += 1;
+            op if op & 0xc7 == 0xc7 => {
+                // call one of the eight restart vectors.
+                self.push(bus, self.pc);
+                self.pc = (op & 0x38) as u16;
             }
-            0x7c => {
-                // load contents of H into A
-                self.a = self.h;
-                self.pc += 1;
+            _ => {
+                // the unused opcodes lock up the CPU, not even interrupts get it going again.
+                self.locked = true;
+                cycles = 1;
             }
-            0x90 => {
-                // A-B store in A
-                let result = self.a.wrapping_sub(self.b);
-                self.set_n(true);
-                self.set_z(result == 0);
-                self.a = result;
-                self.pc += 1;
+        }
+
+        if ime_pending && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+
+        cycles
+    }
+
+    // services the highest priority pending interrupt. takes 5 M-cycles: two idle, two to push PC
+    // and one to jump. if pushing the high byte of PC overwrites IE and cancels every pending
+    // interrupt, the CPU ends up at 0x0000 instead.
+    fn dispatch_interrupt<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        self.ime = false;
+        if self.halt_bug {
+            // EI; HALT with an interrupt pending returns to the HALT itself.
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        let pc = u8s_from_16(self.pc);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(bus, self.sp, pc.0);
+        let pending = bus.pending_interrupts();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(bus, self.sp, pc.1);
+
+        self.pc = match Interrupt::highest(pending) {
+            Some(interrupt) => {
+                bus.acknowledge_interrupt(interrupt);
+                interrupt.vector()
             }
-            0x15 => {
-                // decrement D.
-                let result = self.d.wrapping_sub(1);
-                self.set_n(true);
+            None => 0x0000,
+        };
+
+        5
+    }
+
+    // every memory access takes one M-cycle, the devices on the bus are ticked as it happens so
+    // they see the access in the cycle it lands in.
+    fn read<B: Bus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        let data = bus.read_byte(addr);
+        bus.tick();
+        self.bus_cycles += 1;
+        data
+    }
+
+    fn write<B: Bus>(&mut self, bus: &mut B, addr: u16, data: u8) {
+        bus.write_byte(addr, data);
+        bus.tick();
+        self.bus_cycles += 1;
+    }
+
+    fn fetch_byte<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let data = self.read(bus, self.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        data
+    }
+
+    fn fetch_word<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let lsb = self.fetch_byte(bus);
+        let msb = self.fetch_byte(bus);
+        u16_from_u8s(msb, lsb)
+    }
+
+    fn push<B: Bus>(&mut self, bus: &mut B, data: u16) {
+        let u8s = u8s_from_16(data);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(bus, self.sp, u8s.0);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(bus, self.sp, u8s.1);
+    }
+
+    fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let lsb = self.read(bus, self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        let msb = self.read(bus, self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        u16_from_u8s(msb, lsb)
+    }
+
+    // registers are encoded in the low three bits of an index: B, C, D, E, H, L, (HL), A.
+    fn read_r8<B: Bus>(&mut self, bus: &mut B, index: u8) -> u8 {
+        match index & 0x07 {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.read(bus, self.hl()),
+            _ => self.a,
+        }
+    }
+
+    fn write_r8<B: Bus>(&mut self, bus: &mut B, index: u8, data: u8) {
+        match index & 0x07 {
+            0 => self.b = data,
+            1 => self.c = data,
+            2 => self.d = data,
+            3 => self.e = data,
+            4 => self.h = data,
+            5 => self.l = data,
+            6 => self.write(bus, self.hl(), data),
+            _ => self.a = data,
+        }
+    }
+
+    // register pairs are encoded in the low two bits of an index: BC, DE, HL, SP.
+    fn read_r16(&self, index: u8) -> u16 {
+        match index & 0x03 {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.hl(),
+            _ => self.sp,
+        }
+    }
+
+    fn write_r16(&mut self, index: u8, data: u16) {
+        match index & 0x03 {
+            0 => self.set_bc(data),
+            1 => self.set_de(data),
+            2 => self.set_hl(data),
+            _ => self.sp = data,
+        }
+    }
+
+    // push and pop use AF in place of SP.
+    fn read_r16_stack(&self, index: u8) -> u16 {
+        match index & 0x03 {
+            3 => self.af(),
+            i => self.read_r16(i),
+        }
+    }
+
+    fn write_r16_stack(&mut self, index: u8, data: u16) {
+        match index & 0x03 {
+            3 => self.set_af(data),
+            i => self.write_r16(i, data),
+        }
+    }
+
+    // branch conditions are encoded in bits 3 and 4 of an opcode: NZ, Z, NC, C.
+    fn condition(&self, opcode: u8) -> bool {
+        match (opcode >> 3) & 0x03 {
+            0 => !self.z(),
+            1 => self.z(),
+            2 => !self.carry(),
+            _ => self.carry(),
+        }
+    }
+
+    fn jr<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let jump = self.fetch_byte(bus);
+        if taken {
+            self.pc = self.pc.wrapping_add((jump as i8) as u16);
+        }
+    }
+
+    fn jp<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let addr = self.fetch_word(bus);
+        if taken {
+            self.pc = addr;
+        }
+    }
 
-                self.d = result;
-                self.set_z(result == 0);
+    fn call<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let addr = self.fetch_word(bus);
+        if taken {
+            self.push(bus, self.pc);
+            self.pc = addr;
+        }
+    }
 
-                self.pc += 1;
+    fn ret<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        if taken {
+            self.pc = self.pop(bus);
+        }
+    }
+
+    // arithmetic and logic ops are encoded in bits 3 to 5: ADD, ADC, SUB, SBC, AND, XOR, OR, CP.
+    fn alu(&mut self, op: u8, operand: u8) {
+        match op & 0x07 {
+            0 => self.a = self.add(operand, false),
+            1 => self.a = self.add(operand, self.carry()),
+            2 => self.a = self.sub(operand, false),
+            3 => self.a = self.sub(operand, self.carry()),
+            4 => {
+                self.a &= operand;
+                self.set_z(self.a == 0);
+                self.set_n(false);
+                self.set_hc(true);
+                self.set_carry(false);
             }
-            0x16 => {
-                // load next 8 bits into D.
-                self.d = self.mmu.read_byte(self.pc + 1);
-                self.pc += 2;
+            5 => {
+                self.a ^= operand;
+                self.set_z(self.a == 0);
+                self.set_n(false);
+                self.set_hc(false);
+                self.set_carry(false);
             }
-            // 0xbe => {
-            //     // compare a with contents of HL.
-            //     let operand = self.mmu.read_byte(self.hl());
-            //     let result = self.a.wrapping_sub(operand);
+            6 => {
+                self.a |= operand;
+                self.set_z(self.a == 0);
+                self.set_n(false);
+                self.set_hc(false);
+                self.set_carry(false);
+            }
+            _ => {
+                self.sub(operand, false);
+            }
+        }
+    }
 
-            //     self.set_n(true);
-            //     self.set_z(result == 0);
-            //     self.pc += 1;
-            // }
-            _ => unimplemented!("opcode {:#04x} not implemented", opcode),
+    fn add(&mut self, operand: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = self.a.wrapping_add(operand).wrapping_add(carry);
+        self.set_z(result == 0);
+        self.set_n(false);
+        self.set_hc((self.a & 0x0f) + (operand & 0x0f) + carry > 0x0f);
+        self.set_carry(self.a as u16 + operand as u16 + carry as u16 > 0xff);
+        result
+    }
+
+    fn sub(&mut self, operand: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = self.a.wrapping_sub(operand).wrapping_sub(carry);
+        self.set_z(result == 0);
+        self.set_n(true);
+        self.set_hc((self.a & 0x0f) < (operand & 0x0f) + carry);
+        self.set_carry((self.a as u16) < operand as u16 + carry as u16);
+        result
+    }
+
+    fn inc(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_add(1);
+        self.set_z(result == 0);
+        self.set_n(false);
+        self.set_hc(eight_bit_hc(data, 1));
+        result
+    }
+
+    fn dec(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_sub(1);
+        self.set_z(result == 0);
+        self.set_n(true);
+        self.set_hc(data & 0x0f == 0);
+        result
+    }
+
+    fn add_hl(&mut self, operand: u16) {
+        let hl = self.hl();
+        self.set_n(false);
+        self.set_hc(sixteen_bit_hc(hl, operand));
+        self.set_carry(hl as u32 + operand as u32 > 0xffff);
+        self.set_hl(hl.wrapping_add(operand));
+    }
+
+    // shared by ADD SP,e and LD HL,SP+e. flags come from the unsigned add on the low byte.
+    fn sp_plus_e<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let operand = (self.fetch_byte(bus) as i8) as u16;
+        self.set_z(false);
+        self.set_n(false);
+        self.set_hc((self.sp & 0x000f) + (operand & 0x000f) > 0x000f);
+        self.set_carry((self.sp & 0x00ff) + (operand & 0x00ff) > 0x00ff);
+        self.sp.wrapping_add(operand)
+    }
+
+    fn daa(&mut self) {
+        let mut correction = 0;
+        let mut carry = false;
+        if self.hc() || (!self.n() && (self.a & 0x0f) > 0x09) {
+            correction |= 0x06;
+        }
+        if self.carry() || (!self.n() && self.a > 0x99) {
+            correction |= 0x60;
+            carry = true;
         }
+
+        self.a = if self.n() {
+            self.a.wrapping_sub(correction)
+        } else {
+            self.a.wrapping_add(correction)
+        };
+        self.set_z(self.a == 0);
+        self.set_hc(false);
+        self.set_carry(carry);
+    }
+
+    fn rlc(&mut self, data: u8) -> u8 {
+        let result = data.rotate_left(1);
+        self.set_shift_flags(result, data & 0x80 != 0);
+        result
+    }
+
+    fn rrc(&mut self, data: u8) -> u8 {
+        let result = data.rotate_right(1);
+        self.set_shift_flags(result, data & 0x01 != 0);
+        result
+    }
+
+    fn rl(&mut self, data: u8) -> u8 {
+        let result = (data << 1) | self.carry() as u8;
+        self.set_shift_flags(result, data & 0x80 != 0);
+        result
+    }
+
+    fn rr(&mut self, data: u8) -> u8 {
+        let result = (data >> 1) | ((self.carry() as u8) << 7);
+        self.set_shift_flags(result, data & 0x01 != 0);
+        result
+    }
+
+    fn sla(&mut self, data: u8) -> u8 {
+        let result = data << 1;
+        self.set_shift_flags(result, data & 0x80 != 0);
+        result
+    }
+
+    fn sra(&mut self, data: u8) -> u8 {
+        let result = (data >> 1) | (data & 0x80);
+        self.set_shift_flags(result, data & 0x01 != 0);
+        result
+    }
+
+    fn swap(&mut self, data: u8) -> u8 {
+        let result = data.rotate_left(4);
+        self.set_shift_flags(result, false);
+        result
+    }
+
+    fn srl(&mut self, data: u8) -> u8 {
+        let result = data >> 1;
+        self.set_shift_flags(result, data & 0x01 != 0);
+        result
+    }
+
+    fn set_shift_flags(&mut self, result: u8, carry: bool) {
+        self.set_z(result == 0);
+        self.set_n(false);
+        self.set_hc(false);
+        self.set_carry(carry);
     }
 
     fn z(&self) -> bool {
@@ -424,6 +842,42 @@ impl Cpu {
         }
     }
 
+    fn carry(&self) -> bool {
+        ((self.f & 0b0001_0000) >> 4) == 1
+    }
+
+    fn set_carry(&mut self, bit: bool) {
+        match bit {
+            true => {
+                self.f |= C_FLAG;
+            }
+            false => {
+                self.f &= !C_FLAG;
+            }
+        }
+    }
+
+    fn af(&self) -> u16 {
+        u16_from_u8s(self.a, self.f)
+    }
+
+    fn set_af(&mut self, data: u16) {
+        let u8s = u8s_from_16(data);
+        self.a = u8s.0;
+        // the low nibble of F is not wired up and always reads 0.
+        self.f = u8s.1 & 0xf0;
+    }
+
+    fn bc(&self) -> u16 {
+        u16_from_u8s(self.b, self.c)
+    }
+
+    fn set_bc(&mut self, data: u16) {
+        let u8s = u8s_from_16(data);
+        self.b = u8s.0;
+        self.c = u8s.1;
+    }
+
     fn hl(&self) -> u16 {
         u16_from_u8s(self.h, self.l)
     }
@@ -444,12 +898,42 @@ impl Cpu {
         self.e = u8s.1;
     }
 
-    fn execute_cb(&mut self, opcode: u8) {
-        match opcode {
-            0x7c => {
-                self.set_z((self.h & 0x80) >> 7 == 0);
+    // CB opcodes are decoded from their bit fields: bits 6 and 7 select the group, bits 3 to 5
+    // the rotate/shift op or bit number, and bits 0 to 2 the target register. returns the M-cycles
+    // taken including the prefix, (HL) targets cost extra for the memory accesses.
+    fn execute_cb<B: Bus>(&mut self, bus: &mut B, opcode: u8) -> u8 {
+        let bit = (opcode >> 3) & 0x07;
+        let data = self.read_r8(bus, opcode);
+
+        match opcode >> 6 {
+            0 => {
+                let result = match bit {
+                    0 => self.rlc(data),
+                    1 => self.rrc(data),
+                    2 => self.rl(data),
+                    3 => self.rr(data),
+                    4 => self.sla(data),
+                    5 => self.sra(data),
+                    6 => self.swap(data),
+                    _ => self.srl(data),
+                };
+                self.write_r8(bus, opcode, result);
+            }
+            1 => {
+                // BIT only reads its target, so (HL) takes one cycle less than the others.
+                self.set_z(data & (1 << bit) == 0);
+                self.set_n(false);
+                self.set_hc(true);
+                return if opcode & 0x07 == 6 { 3 } else { 2 };
             }
-            _ => unimplemented!("0xcb opcode {:#04x} not implemented", opcode),
+            2 => self.write_r8(bus, opcode, data & !(1 << bit)),
+            _ => self.write_r8(bus, opcode, data | (1 << bit)),
+        }
+
+        if opcode & 0x07 == 6 {
+            4
+        } else {
+            2
         }
     }
 }
@@ -480,15 +964,323 @@ fn eight_bit_hc(a: u8, b: u8) -> bool {
 }
 
 fn sixteen_bit_hc(a: u16, b: u16) -> bool {
-    true
+    (((a & 0xFFF) + (b & 0xFFF)) & 0x1000) == 0x1000
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bus::FlatBus;
+    use crate::joypad::Button;
+    use crate::mmu::Mmu;
+
+    fn cpu_with_program(program: &[u8]) -> (Cpu, FlatBus) {
+        let mut bus = FlatBus::new();
+        bus.memory[..program.len()].copy_from_slice(program);
+        (Cpu::new(), bus)
+    }
 
     #[test]
     fn test_u16_from_u8s() {
         assert_eq!(u16_from_u8s(0xff, 0xfe), 0xfffe);
     }
+
+    #[test]
+    fn test_sixteen_bit_hc() {
+        assert!(sixteen_bit_hc(0x0fff, 0x0001));
+        assert!(!sixteen_bit_hc(0x0ffe, 0x0001));
+    }
+
+    #[test]
+    fn test_skip_boot_registers() {
+        let cpu = Cpu::skip_boot(Model::Dmg);
+        assert_eq!(cpu.af(), 0x01b0);
+        assert_eq!(cpu.bc(), 0x0013);
+        assert_eq!(cpu.de(), 0x00d8);
+        assert_eq!(cpu.hl(), 0x014d);
+        assert_eq!(cpu.sp, 0xfffe);
+        assert_eq!(cpu.pc, 0x0100);
+
+        assert_eq!(Cpu::skip_boot(Model::Cgb).af(), 0x1180);
+    }
+
+    #[test]
+    fn test_add_sets_flags() {
+        // LD A,0x3a; ADD A,0xc6
+        let (mut cpu, mut bus) = cpu_with_program(&[0x3e, 0x3a, 0xc6, 0xc6]);
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.z());
+        assert!(!cpu.n());
+        assert!(cpu.hc());
+        assert!(cpu.carry());
+    }
+
+    #[test]
+    fn test_call_and_ret() {
+        // LD SP,0xfffe; CALL 0x0010; ... 0x0010: RET
+        let (mut cpu, mut bus) = cpu_with_program(&[0x31, 0xfe, 0xff, 0xcd, 0x10, 0x00]);
+        bus.memory[0x10] = 0xc9;
+
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+        assert_eq!(cpu.pc, 0x0010);
+        assert_eq!(cpu.sp, 0xfffc);
+
+        cpu.execute(&mut bus);
+        assert_eq!(cpu.pc, 0x0006);
+        assert_eq!(cpu.sp, 0xfffe);
+    }
+
+    #[test]
+    fn test_daa_after_add() {
+        // LD A,0x45; ADD A,0x38; DAA
+        let (mut cpu, mut bus) = cpu_with_program(&[0x3e, 0x45, 0xc6, 0x38, 0x27]);
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+
+        assert_eq!(cpu.a, 0x83);
+        assert!(!cpu.carry());
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        // XOR A; JR NZ,+2; JR Z,+0; CALL Z,0x0010; ... 0x0010: RET NZ; RET
+        let (mut cpu, mut bus) =
+            cpu_with_program(&[0xaf, 0x20, 0x02, 0x28, 0x00, 0xcc, 0x10, 0x00]);
+        bus.memory[0x10..0x12].copy_from_slice(&[0xc0, 0xc9]);
+        cpu.sp = 0xfffe;
+
+        assert_eq!(cpu.execute(&mut bus), 1);
+        assert_eq!(cpu.execute(&mut bus), 2);
+        assert_eq!(cpu.execute(&mut bus), 3);
+        assert_eq!(cpu.execute(&mut bus), 6);
+        assert_eq!(cpu.execute(&mut bus), 2);
+        assert_eq!(cpu.execute(&mut bus), 4);
+        assert_eq!(cpu.pc, 0x0008);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        // EI; NOP; NOP
+        let (mut cpu, mut bus) = cpu_with_program(&[0xfb, 0x00, 0x00]);
+        cpu.sp = 0xfffe;
+        bus.write_byte(0xffff, 0x1f);
+        bus.request_interrupt(Interrupt::Timer);
+        bus.request_interrupt(Interrupt::Joypad);
+
+        // the instruction after EI still runs before the interrupt is taken.
+        assert_eq!(cpu.execute(&mut bus), 1);
+        assert_eq!(cpu.execute(&mut bus), 1);
+        assert_eq!(cpu.execute(&mut bus), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.sp, 0xfffc);
+        assert_eq!(bus.read_byte(0xfffc), 0x02);
+        assert!(!cpu.ime);
+        assert_eq!(bus.read_byte(0xff0f), Interrupt::Joypad.mask());
+    }
+
+    #[test]
+    fn test_interrupt_cancelled_by_push_to_ie() {
+        let (mut cpu, mut bus) = cpu_with_program(&[]);
+        cpu.ime = true;
+        cpu.pc = 0x0234;
+        cpu.sp = 0x0000;
+        bus.write_byte(0xffff, 0x01);
+        bus.request_interrupt(Interrupt::VBlank);
+
+        // the high byte of PC lands in IE and disables VBlank before it can be serviced.
+        assert_eq!(cpu.execute(&mut bus), 5);
+        assert_eq!(bus.read_byte(0xffff), 0x02);
+        assert_eq!(cpu.pc, 0x0000);
+    }
+
+    #[test]
+    fn test_di_cancels_pending_ei() {
+        // EI; DI; NOP
+        let (mut cpu, mut bus) = cpu_with_program(&[0xfb, 0xf3, 0x00]);
+        bus.write_byte(0xffff, 0x01);
+        bus.request_interrupt(Interrupt::VBlank);
+
+        for _ in 0..3 {
+            assert_eq!(cpu.execute(&mut bus), 1);
+        }
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0x0003);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_dispatch_when_ime_off() {
+        // HALT; INC A
+        let (mut cpu, mut bus) = cpu_with_program(&[0x76, 0x3c]);
+        bus.write_byte(0xffff, 0x04);
+
+        cpu.execute(&mut bus);
+        assert!(cpu.halted);
+        assert_eq!(cpu.execute(&mut bus), 1);
+        assert_eq!(cpu.pc, 0x0001);
+
+        bus.request_interrupt(Interrupt::Timer);
+        cpu.execute(&mut bus);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(cpu.pc, 0x0002);
+    }
+
+    #[test]
+    fn test_halt_bug_repeats_next_byte() {
+        // HALT; INC A
+        let (mut cpu, mut bus) = cpu_with_program(&[0x76, 0x3c]);
+        bus.write_byte(0xffff, 0x04);
+        bus.request_interrupt(Interrupt::Timer);
+
+        cpu.execute(&mut bus);
+        assert!(!cpu.halted);
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+        assert_eq!(cpu.a, 0x02);
+        assert_eq!(cpu.pc, 0x0002);
+    }
+
+    #[test]
+    fn test_stop_speed_switch() {
+        // STOP 0x00; STOP 0x00
+        let (mut cpu, mut bus) = cpu_with_program(&[0x10, 0x00, 0x10, 0x00]);
+        cpu.model = Model::Cgb;
+        bus.write_byte(0xff4d, 0x01);
+        bus.write_byte(0xff00, 0xff);
+
+        cpu.execute(&mut bus);
+        assert!(!cpu.stopped);
+        assert_eq!(bus.read_byte(0xff4d), 0x80);
+        assert_eq!(cpu.pc, 0x0002);
+
+        cpu.execute(&mut bus);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.execute(&mut bus), 1);
+        assert_eq!(cpu.pc, 0x0004);
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_up() {
+        // illegal 0xd3; EI
+        let (mut cpu, mut bus) = cpu_with_program(&[0xd3, 0xfb]);
+        cpu.ime = true;
+        assert_eq!(cpu.execute(&mut bus), 1);
+        bus.request_interrupt(Interrupt::VBlank);
+        bus.write_byte(0xffff, 0x01);
+        for _ in 0..4 {
+            assert_eq!(cpu.execute(&mut bus), 1);
+        }
+        assert_eq!(cpu.pc, 0x0001);
+    }
+
+    #[test]
+    fn test_stop_speed_switch_through_mmu() {
+        let mut mmu = Mmu::with_model(Model::Cgb);
+        // STOP 0x00; STOP 0x00
+        for (i, &data) in [0x10, 0x00, 0x10, 0x00].iter().enumerate() {
+            mmu.write_byte(0xc000 + i as u16, data);
+        }
+        let mut cpu = Cpu::with_model(Model::Cgb);
+        cpu.pc = 0xc000;
+        assert_eq!(mmu.read_byte(0xff4d), 0x7e);
+
+        mmu.write_byte(0xff4d, 0xff);
+        assert_eq!(mmu.read_byte(0xff4d), 0x7f);
+        assert_eq!(cpu.execute(&mut mmu), 2);
+        assert!(mmu.double_speed());
+        assert!(!cpu.stopped);
+        assert_eq!(mmu.read_byte(0xff4d), 0xfe);
+
+        // nothing armed, so this one stops.
+        cpu.execute(&mut mmu);
+        assert!(mmu.double_speed());
+        assert!(cpu.stopped);
+    }
+
+    #[test]
+    fn test_execute_ticks_every_m_cycle() {
+        let mut mmu = Mmu::new();
+        // JR -2, two accesses and an idle cycle each time round.
+        mmu.write_byte(0xc000, 0x18);
+        mmu.write_byte(0xc001, 0xfe);
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xc000;
+
+        for _ in 0..1000 {
+            assert_eq!(cpu.execute(&mut mmu), 3);
+        }
+        // 3000 M-cycles are 12000 clocks, DIV counts every 256.
+        assert_eq!(mmu.read_byte(0xff04), 46);
+    }
+
+    #[test]
+    fn test_stop_wakes_on_joypad_lines() {
+        let mut mmu = Mmu::new();
+        // STOP 0x00; NOP
+        for (i, &data) in [0x10, 0x00, 0x00].iter().enumerate() {
+            mmu.write_byte(0xc000 + i as u16, data);
+        }
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xc000;
+
+        // a press nobody serviced leaves the joypad bit in IF set.
+        mmu.write_byte(0xff00, 0x10);
+        mmu.press(Button::Start);
+        mmu.release(Button::Start);
+        assert_ne!(mmu.read_byte(0xff0f) & Interrupt::Joypad.mask(), 0);
+
+        cpu.execute(&mut mmu);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.execute(&mut mmu), 1);
+        assert_eq!(cpu.pc, 0xc002);
+
+        mmu.press(Button::A);
+        cpu.execute(&mut mmu);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, 0xc003);
+    }
+
+    #[test]
+    fn test_cb_swap_and_bit() {
+        // LD B,0xf0; SWAP B; BIT 7,B
+        let (mut cpu, mut bus) = cpu_with_program(&[0x06, 0xf0, 0xcb, 0x30, 0xcb, 0x78]);
+        cpu.execute(&mut bus);
+        cpu.execute(&mut bus);
+        assert_eq!(cpu.b, 0x0f);
+        assert!(!cpu.carry());
+
+        cpu.execute(&mut bus);
+        assert!(cpu.z());
+        assert!(cpu.hc());
+    }
+
+    #[test]
+    fn test_cb_res_set_hl() {
+        // LD HL,0xc000
+        let (mut cpu, mut bus) = cpu_with_program(&[0x21, 0x00, 0xc0]);
+        cpu.execute(&mut bus);
+        assert_eq!(cpu.execute_cb(&mut bus, 0xde), 4);
+        assert_eq!(bus.memory[0xc000], 0x08);
+        assert_eq!(cpu.execute_cb(&mut bus, 0x9e), 4);
+        assert_eq!(bus.memory[0xc000], 0x00);
+        assert_eq!(cpu.execute_cb(&mut bus, 0x46), 3);
+    }
+
+    #[test]
+    fn test_pop_af_masks_low_nibble() {
+        // LD SP,0xc000; LD BC,0x12ff; PUSH BC; POP AF
+        let (mut cpu, mut bus) =
+            cpu_with_program(&[0x31, 0x00, 0xc0, 0x01, 0xff, 0x12, 0xc5, 0xf1]);
+        for _ in 0..4 {
+            cpu.execute(&mut bus);
+        }
+
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.f, 0xf0);
+    }
 }
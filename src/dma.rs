@@ -0,0 +1,78 @@
+pub const DMA_ADDR: u16 = 0xff46;
+pub const DMA_LENGTH: u16 = 0xa0; // bytes copied, one per M-cycle
+
+// OAM DMA. copies 160 bytes from a 256 byte aligned source into OAM. while it runs the CPU can
+// only reach HRAM and the registers on its internal bus.
+#[derive(Debug, Default)]
+pub struct Dma {
+    source: Option<u16>,  // source of the running transfer
+    index: u16,           // next byte of the running transfer
+    pending: Option<u16>, // a transfer starts the M-cycle after the write to 0xff46
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            source: None,
+            index: 0,
+            pending: None,
+        }
+    }
+
+    // writing 0xff46 again while a transfer runs restarts it. the old transfer keeps going
+    // until the new one takes over, so the bus stays blocked across the restart.
+    pub fn start(&mut self, page: u8) {
+        self.pending = Some((page as u16) << 8);
+    }
+
+    pub fn active(&self) -> bool {
+        self.source.is_some()
+    }
+
+    // advances one M-cycle, returning the source address and OAM offset of the byte to copy.
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let transfer = self.source.map(|source| (source + self.index, self.index));
+        if transfer.is_some() {
+            self.index += 1;
+            if self.index == DMA_LENGTH {
+                self.source = None;
+            }
+        }
+        if let Some(source) = self.pending.take() {
+            self.source = Some(source);
+            self.index = 0;
+        }
+        transfer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_takes_160_cycles() {
+        let mut dma = Dma::new();
+        dma.start(0xc1);
+        assert_eq!(dma.tick(), None);
+        assert!(dma.active());
+        for i in 0..DMA_LENGTH {
+            assert!(dma.active());
+            assert_eq!(dma.tick(), Some((0xc100 + i, i)));
+        }
+        assert!(!dma.active());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut dma = Dma::new();
+        dma.start(0xc1);
+        for _ in 0..11 {
+            dma.tick();
+        }
+        dma.start(0xd0);
+        assert_eq!(dma.tick(), Some((0xc10a, 0x0a)));
+        assert_eq!(dma.tick(), Some((0xd000, 0x00)));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::boot::BootRom;
+use crate::cartridge::{Cartridge, RumbleEvent};
+use crate::cpu;
+use crate::joypad::Button;
+use crate::mmu;
+use crate::model::Model;
+use crate::ppu;
+use crate::serial::SerialLink;
+
+pub const CYCLES_PER_FRAME: u32 = 70224; // 154 lines of 456 dots
+const DOTS_PER_M_CYCLE: u32 = 4;
+
+pub struct GameBoy {
+    cpu: cpu::Cpu,
+    ppu: ppu::Ppu,
+    mmu: mmu::Mmu,
+    clocks: u32, // dots elapsed in the current frame
+}
+
+impl Default for GameBoy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameBoy {
+    pub fn new() -> Self {
+        Self::skip_boot(Model::Dmg)
+    }
+
+    // starts from 0x0100 with the hardware set up the way the boot ROM would have left it.
+    pub fn skip_boot(model: Model) -> Self {
+        GameBoy {
+            cpu: cpu::Cpu::skip_boot(model),
+            ppu: ppu::Ppu::skip_boot(),
+            mmu: mmu::Mmu::skip_boot(model),
+            clocks: 0,
+        }
+    }
+
+    // starts from 0x0000 with the boot ROM mapped, on the model the boot ROM belongs to.
+    pub fn with_boot_rom(boot_rom: BootRom) -> Self {
+        GameBoy {
+            cpu: cpu::Cpu::with_model(boot_rom.model()),
+            ppu: ppu::Ppu::new(),
+            mmu: mmu::Mmu::with_boot_rom(boot_rom),
+            clocks: 0,
+        }
+    }
+
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.mmu.insert_cartridge(cartridge);
+    }
+
+    // plugs something into the link port, it starts out disconnected.
+    pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+        self.mmu.connect_serial(link);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmu.press(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmu.release(button);
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.mmu.cartridge_mut()
+    }
+
+    // rumble motor transitions since the last call, oldest first.
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        self.mmu.take_rumble_events()
+    }
+
+    // runs one instruction and advances the rest of the system by the time it took.
+    pub fn step(&mut self) -> u8 {
+        let cycles = self.cpu.execute(&mut self.mmu);
+        // in double speed the CPU runs twice as fast as the PPU.
+        let dots = if self.mmu.double_speed() {
+            cycles as u32 * DOTS_PER_M_CYCLE / 2
+        } else {
+            cycles as u32 * DOTS_PER_M_CYCLE
+        };
+        self.mmu.tick_cartridge(dots);
+        for _ in 0..dots {
+            self.ppu.tick(&mut self.mmu);
+        }
+        self.clocks += dots;
+
+        cycles
+    }
+
+    pub fn run_frame(&mut self) {
+        while self.clocks < CYCLES_PER_FRAME {
+            self.step();
+        }
+        self.clocks -= CYCLES_PER_FRAME;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_ppu_advances_four_dots_per_m_cycle() {
+        let mut gb = GameBoy::new();
+        // the rest of the last VBlank line, where LY already reads 0, then line 0.
+        gb.step();
+        assert_eq!(gb.mmu.read_byte(0xff41) & 0x03, 1);
+        while gb.clocks < 2 * 456 {
+            assert_eq!(gb.mmu.read_byte(0xff44), 0);
+            let clocks = gb.clocks;
+            let cycles = gb.step();
+            assert_eq!(gb.clocks - clocks, cycles as u32 * 4);
+        }
+        assert_eq!(gb.mmu.read_byte(0xff44), 1);
+    }
+
+    #[test]
+    fn test_rumble_events_reach_the_frontend() {
+        let mut gb = GameBoy::new();
+        let rom = crate::cartridge::test_rom(0x1c, 0x00, 0x00);
+        gb.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+        gb.mmu.write_byte(0x4000, 0x08);
+        gb.mmu.write_byte(0x4000, 0x00);
+        assert_eq!(
+            gb.take_rumble_events(),
+            vec![RumbleEvent::MotorOn, RumbleEvent::MotorOff]
+        );
+    }
+
+    #[test]
+    fn test_serial_capture() {
+        use crate::serial::Capture;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut rom = crate::cartridge::test_rom(0x00, 0x00, 0x00);
+        // ld a, 'H'; ldh (SB), a; ld a, 0x81; ldh (SC), a; jr -2
+        rom[0x100..0x10a]
+            .copy_from_slice(&[0x3e, 0x48, 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02, 0x18, 0xfe]);
+        let mut gb = GameBoy::new();
+        gb.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+        let output = Rc::new(RefCell::new(Vec::new()));
+        gb.connect_serial(Box::new(Capture::new(output.clone())));
+
+        gb.run_frame();
+        assert_eq!(*output.borrow(), b"H");
+        assert_eq!(gb.mmu.read_byte(0xff0f) & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_tima_write_around_reload() {
+        // xor a; ld (DIV), a; ld a, 0xfe; ldh (TIMA), a; ld a, 0x05; ldh (TAC), a; ld a, 0x42;
+        // then some NOPs and ldh (TIMA), a. TIMA overflows 15 M-cycles after DIV is reset, the
+        // NOPs move the last write onto the cycle before, the cycle after and the one after that.
+        let program = [
+            0xaf, 0xea, 0x04, 0xff, 0x3e, 0xfe, 0xe0, 0x05, 0x3e, 0x05, 0xe0, 0x07, 0x3e, 0x42,
+        ];
+        for (nops, tima, interrupt) in [(0, 0x43, 0), (1, 0x42, 0), (2, 0x00, 0x04)] {
+            let mut rom = crate::cartridge::test_rom(0x00, 0x00, 0x00);
+            rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+            let write = 0x100 + program.len() + nops;
+            rom[write..write + 2].copy_from_slice(&[0xe0, 0x05]);
+            let mut gb = GameBoy::new();
+            gb.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+
+            for _ in 0..8 + nops {
+                gb.step();
+            }
+            assert_eq!(gb.mmu.read_byte(0xff05), tima);
+            assert_eq!(gb.mmu.read_byte(0xff0f) & 0x04, interrupt);
+        }
+    }
+
+    #[test]
+    fn test_run_frame_lasts_one_frame() {
+        let mut gb = GameBoy::new();
+        gb.run_frame();
+        assert!(gb.clocks < 24);
+        assert_eq!(gb.mmu.read_byte(0xff44), 0);
+    }
+}
@@ -0,0 +1,49 @@
+pub const IF_ADDR: u16 = 0xff0f; // interrupt flag, one bit per requested interrupt
+pub const IE_ADDR: u16 = 0xffff; // interrupt enable, one bit per enabled interrupt
+
+// interrupts in priority order, the discriminant is the bit in IE and IF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank = 0,
+    Stat = 1,
+    Timer = 2,
+    Serial = 3,
+    Joypad = 4,
+}
+
+const PRIORITY: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::Stat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    // addr the CPU jumps to when servicing this interrupt.
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+
+    // the highest priority interrupt set in a mask of pending interrupts.
+    pub fn highest(pending: u8) -> Option<Interrupt> {
+        PRIORITY.into_iter().find(|i| pending & i.mask() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highest_priority() {
+        assert_eq!(Interrupt::highest(0b1_0100), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::highest(0b1_0000), Some(Interrupt::Joypad));
+        assert_eq!(Interrupt::highest(0b1110_0000), None);
+        assert_eq!(Interrupt::Serial.vector(), 0x58);
+    }
+}
@@ -0,0 +1,116 @@
+pub const P1_ADDR: u16 = 0xff00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // bit in the pressed set. the low nibble is read through P14, the high one through P15.
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// the buttons sit in a 2x4 matrix. writing 0 to P14 (bit 4) or P15 (bit 5) selects the
+// directions or the other buttons, and a pressed button pulls its line in the low nibble to 0.
+#[derive(Debug)]
+pub struct Joypad {
+    select: u8, // bits 4 and 5 as last written
+    pressed: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: 0x00,
+            pressed: 0,
+        }
+    }
+
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= self.pressed & 0x0f;
+        }
+        if self.select & 0x20 == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & 0x0f
+    }
+
+    pub fn line_low(&self) -> bool {
+        self.lines() != 0x0f
+    }
+
+    pub fn read(&self) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+
+    // each of these returns true when a line went from high to low, which requests the joypad
+    // interrupt.
+    pub fn write(&mut self, data: u8) -> bool {
+        self.update(|joypad| joypad.select = data & 0x30)
+    }
+
+    pub fn press(&mut self, button: Button) -> bool {
+        self.update(|joypad| joypad.pressed |= button.mask())
+    }
+
+    pub fn release(&mut self, button: Button) -> bool {
+        self.update(|joypad| joypad.pressed &= !button.mask())
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Self)) -> bool {
+        let lines = self.lines();
+        f(self);
+        lines & !self.lines() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_lines() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x30);
+        joypad.press(Button::Down);
+        joypad.press(Button::A);
+        assert_eq!(joypad.read(), 0xff);
+
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xe7);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xde);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xc6);
+    }
+
+    #[test]
+    fn test_interrupt_on_falling_edge() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        // directions aren't selected, so nothing changes on the lines.
+        assert!(!joypad.press(Button::Up));
+        assert!(joypad.press(Button::Start));
+        assert!(!joypad.release(Button::Start));
+
+        // selecting a row with a button already down pulls its line low too.
+        assert!(joypad.write(0x20));
+    }
+}
@@ -1,4 +1,14 @@
+pub mod boot;
+pub mod bus;
+pub mod cartridge;
 pub mod cpu;
+pub mod dma;
+pub mod gameboy;
+pub mod interrupt;
+pub mod joypad;
 pub mod mmu;
+pub mod model;
 pub mod ppu;
+pub mod serial;
+pub mod timer;
 pub mod utils;
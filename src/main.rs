@@ -1,15 +1,115 @@
-use dmg::cpu;
-use dmg::mmu;
-use dmg::ppu;
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use dmg::boot;
+use dmg::cartridge;
+use dmg::gameboy;
+use dmg::model::Model;
+
+const SAVE_INTERVAL: u32 = 60; // frames between flushes of battery backed RAM
+
+fn usage() -> ! {
+    eprintln!("usage: dmg [--boot-rom <path> | --model <dmg|cgb>] [--host-rtc] <rom>");
+    process::exit(2);
+}
 
 fn main() {
-    let mut cpu = cpu::Cpu::new();
-    let mut ppu = ppu::Ppu::new();
-    let mut mmu = mmu::Mmu::new();
-
-    loop {
-        cpu.execute(&mut mmu);
-        ppu.tick(&mut mmu);
-        //println!("{:?}", cpu);
+    let mut rom_path = None;
+    let mut boot_rom_path = None;
+    let mut model = None;
+    let mut host_rtc = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--boot-rom" => boot_rom_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--model" => {
+                model = match args.next().as_deref() {
+                    Some("dmg") => Some(Model::Dmg),
+                    Some("cgb") => Some(Model::Cgb),
+                    _ => usage(),
+                }
+            }
+            "--host-rtc" => host_rtc = true,
+            _ if rom_path.is_none() && !arg.starts_with('-') => rom_path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| usage());
+    // a boot ROM already decides the model.
+    if boot_rom_path.is_some() && model.is_some() {
+        usage();
+    }
+
+    let mut cartridge = match cartridge::Cartridge::from_file(&rom_path) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            eprintln!("{}: {}", rom_path, err);
+            process::exit(1);
+        }
+    };
+    if host_rtc {
+        cartridge.set_rtc_sync(cartridge::RtcSync::Host);
+    }
+    let save_path = Path::new(&rom_path).with_extension("sav");
+    if cartridge.has_battery() && save_path.exists() {
+        // refuse to start rather than overwrite a save we couldn't read.
+        if let Err(err) = cartridge.load_save_file(&save_path) {
+            eprintln!("{}: {}", save_path.display(), err);
+            process::exit(1);
+        }
+    }
+    let header = cartridge.header();
+    eprintln!(
+        "loaded {:?} (type {:#04x})",
+        header.title, header.cartridge_type
+    );
+    if !cartridge.global_checksum_valid() {
+        eprintln!("{}: warning: global checksum does not match", rom_path);
+    }
+
+    let mut gameboy = match boot_rom_path {
+        Some(path) => match boot::BootRom::from_file(&path) {
+            Ok(boot_rom) => gameboy::GameBoy::with_boot_rom(boot_rom),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => gameboy::GameBoy::skip_boot(model.unwrap_or(Model::Dmg)),
+    };
+    gameboy.insert_cartridge(cartridge);
+
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst)) {
+        eprintln!("warning: saves will only be written periodically: {}", err);
+    }
+
+    let mut frames = 0u32;
+    while running.load(Ordering::SeqCst) {
+        gameboy.run_frame();
+        frames += 1;
+        if frames == SAVE_INTERVAL {
+            frames = 0;
+            flush_save(&mut gameboy, &save_path, false);
+        }
+    }
+    flush_save(&mut gameboy, &save_path, true);
+}
+
+// writes battery backed RAM out if it changed, or unconditionally on exit so the clock is current.
+fn flush_save(gameboy: &mut gameboy::GameBoy, path: &Path, exiting: bool) {
+    let Some(cartridge) = gameboy.cartridge_mut() else {
+        return;
+    };
+    if !cartridge.has_battery() || !(exiting || cartridge.save_pending()) {
+        return;
+    }
+    if let Err(err) = cartridge.write_save_file(path) {
+        eprintln!("{}: {}", path.display(), err);
     }
 }
@@ -1,30 +1,519 @@
-pub const MEM_SIZE: usize = 0x10000; // 2^16, 65536
+use crate::boot::BootRom;
+use crate::bus::Bus;
+use crate::cartridge::{Cartridge, RumbleEvent};
+use crate::dma::{self, Dma};
+use crate::interrupt::{self, Interrupt};
+use crate::joypad::{self, Button, Joypad};
+use crate::model::{Model, KEY1_ADDR};
+use crate::serial::{self, Serial, SerialLink};
+use crate::timer::{self, Timer};
+
+const VRAM_SIZE: usize = 0x2000;
+const WRAM_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xa0;
+const IO_SIZE: usize = 0x80;
+const HRAM_SIZE: usize = 0x7f;
+
+const STAT_ADDR: u16 = 0xff41;
+const LY_ADDR: u16 = 0xff44;
+const BOOT_ADDR: u16 = 0xff50; // writing non-zero unmaps the boot ROM
+
+// bits of each I/O register that are not wired up and always read as 1 on DMG. registers that
+// don't exist at all read as 0xff.
+#[rustfmt::skip]
+const IO_UNUSED_BITS: [u8; IO_SIZE] = [
+    0xc0, 0x00, 0x7e, 0xff, 0x00, 0x00, 0x00, 0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xe0, // 0xff00
+    0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00, 0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff, // 0xff10
+    0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff20
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff30
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, // 0xff40
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff50
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff60
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff70
+];
+
+// I/O registers as the DMG boot ROM leaves them. everything not listed is 0, the registers
+// devices model themselves are set up by those devices.
+const POST_BOOT_IO: [(u16, u8); 26] = [
+    (0xff10, 0x80), // NR10
+    (0xff11, 0xbf), // NR11
+    (0xff12, 0xf3), // NR12
+    (0xff13, 0xff), // NR13
+    (0xff14, 0xbf), // NR14
+    (0xff16, 0x3f), // NR21
+    (0xff18, 0xff), // NR23
+    (0xff19, 0xbf), // NR24
+    (0xff1a, 0x7f), // NR30
+    (0xff1b, 0xff), // NR31
+    (0xff1c, 0x9f), // NR32
+    (0xff1d, 0xff), // NR33
+    (0xff1e, 0xbf), // NR34
+    (0xff20, 0xff), // NR41
+    (0xff23, 0xbf), // NR44
+    (0xff24, 0x77), // NR50
+    (0xff25, 0xf3), // NR51
+    (0xff26, 0xf1), // NR52
+    (0xff40, 0x91), // LCDC
+    (0xff41, 0x85), // STAT
+    (0xff46, 0xff), // DMA
+    (0xff47, 0xfc), // BGP
+    (0xff48, 0xff), // OBP0
+    (0xff49, 0xff), // OBP1
+    (0xff4a, 0x00), // WY
+    (0xff4b, 0x00), // WX
+];
+
+// registers the CGB boot ROM leaves differently, applied over the DMG table.
+const CGB_POST_BOOT_IO: [(u16, u8); 1] = [
+    (0xff46, 0x00), // DMA
+];
 
 #[derive(Debug)]
 pub struct Mmu {
-    pub memory: [u8; MEM_SIZE],
+    model: Model,
+    boot_rom: Option<BootRom>, // overlays the start of cartridge ROM until disabled
+    cartridge: Option<Cartridge>, // 0x0000 - 0x7fff and 0xa000 - 0xbfff
+    vram: [u8; VRAM_SIZE],     // 0x8000 - 0x9fff
+    wram: [u8; WRAM_SIZE],     // 0xc000 - 0xdfff, mirrored at 0xe000 - 0xfdff
+    oam: [u8; OAM_SIZE],       // 0xfe00 - 0xfe9f
+    io: [u8; IO_SIZE],         // 0xff00 - 0xff7f
+    hram: [u8; HRAM_SIZE],     // 0xff80 - 0xfffe
+    dma: Dma,
+    joypad: Joypad,
+    serial: Serial,
+    timer: Timer,
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    speed_switch_armed: bool, // KEY1 bit 0, CGB only
+    double_speed: bool,       // KEY1 bit 7, CGB only
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Mmu {
     pub fn new() -> Self {
-        let mut mmu = Mmu {
-            memory: [0; MEM_SIZE],
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Mmu {
+            model,
+            boot_rom: None,
+            cartridge: None,
+            vram: [0; VRAM_SIZE],
+            wram: [0; WRAM_SIZE],
+            oam: [0; OAM_SIZE],
+            io: [0; IO_SIZE],
+            hram: [0; HRAM_SIZE],
+            dma: Dma::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            speed_switch_armed: false,
+            double_speed: false,
+        }
+    }
+
+    // I/O as the boot ROM leaves it, for starting a cartridge at 0x0100 directly.
+    pub fn skip_boot(model: Model) -> Self {
+        let mut mmu = Self::with_model(model);
+        let cgb: &[(u16, u8)] = match model {
+            Model::Dmg => &[],
+            Model::Cgb => &CGB_POST_BOOT_IO,
         };
+        for &(addr, data) in POST_BOOT_IO.iter().chain(cgb) {
+            mmu.io[(addr - 0xff00) as usize] = data;
+        }
+        mmu.timer = Timer::skip_boot(model);
+        mmu.interrupt_flag = Interrupt::VBlank.mask();
+        mmu
+    }
 
-        let rom = include_bytes!("dmg_boot.bin");
+    pub fn with_boot_rom(boot_rom: BootRom) -> Self {
+        let model = boot_rom.model();
+        Mmu {
+            boot_rom: Some(boot_rom),
+            ..Self::with_model(model)
+        }
+    }
 
-        mmu.memory[..rom.len()].copy_from_slice(rom);
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
 
-        mmu
+    // advances the cartridge's clock by clocks of the 4 MiHz system clock. the other devices are
+    // ticked by the CPU through the bus, one M-cycle at a time.
+    pub fn tick_cartridge(&mut self, clocks: u32) {
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.tick(clocks);
+        }
+    }
+
+    // VRAM and OAM as the PPU sees them, it isn't locked out by DMA the way the CPU is.
+    pub fn read_video(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9fff => self.vram[(addr - 0x8000) as usize],
+            0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize],
+            _ => 0xff,
+        }
+    }
+
+    pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+        self.serial.connect(link);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        if self.joypad.release(button) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    // true once a CGB has switched into double speed mode, KEY1 bit 7.
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        self.cartridge
+            .as_mut()
+            .map_or_else(Vec::new, |c| c.take_rumble_events())
+    }
+
+    // LY is read-only to the CPU, the PPU updates it through here.
+    pub fn set_ly(&mut self, ly: u8) {
+        self.io[(LY_ADDR - 0xff00) as usize] = ly;
+    }
+
+    // the mode and LY=LYC bits of STAT are read-only to the CPU, the PPU updates them through here.
+    pub fn set_stat(&mut self, stat: u8) {
+        self.io[(STAT_ADDR - 0xff00) as usize] = stat;
+    }
+
+    fn read_io(&self, addr: u16) -> u8 {
+        let index = (addr - 0xff00) as usize;
+        match addr {
+            interrupt::IF_ADDR => 0xe0 | self.interrupt_flag,
+            joypad::P1_ADDR => self.joypad.read(),
+            serial::SB_ADDR | serial::SC_ADDR => self.serial.read(addr),
+            timer::DIV_ADDR..=timer::TAC_ADDR => self.timer.read(addr),
+            KEY1_ADDR if self.model == Model::Cgb => {
+                0x7e | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+            }
+            _ => self.io[index] | IO_UNUSED_BITS[index],
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, data: u8) {
+        let index = (addr - 0xff00) as usize;
+        match addr {
+            interrupt::IF_ADDR => self.interrupt_flag = data & 0x1f,
+            joypad::P1_ADDR => {
+                if self.joypad.write(data) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            serial::SB_ADDR | serial::SC_ADDR => self.serial.write(addr, data),
+            timer::DIV_ADDR..=timer::TAC_ADDR => self.timer.write(addr, data),
+            LY_ADDR => {}
+            KEY1_ADDR if self.model == Model::Cgb => self.speed_switch_armed = data & 0x01 != 0,
+            BOOT_ADDR => {
+                if data != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            STAT_ADDR => self.io[index] = (data & 0x78) | (self.io[index] & 0x07),
+            dma::DMA_ADDR => {
+                self.io[index] = data;
+                self.dma.start(data);
+            }
+            _ => self.io[index] = data,
+        }
+    }
+
+    // the PPU has VRAM to itself while drawing, and OAM while searching as well. with the LCD
+    // off the mode bits read 0 so neither is locked.
+    fn video_locked(&self, addr: u16) -> bool {
+        let mode = self.io[(STAT_ADDR - 0xff00) as usize] & 0x03;
+        match addr {
+            0x8000..=0x9fff => mode == 3,
+            0xfe00..=0xfe9f => mode >= 2,
+            _ => false,
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff => match self.boot_rom.as_ref().and_then(|b| b.read(addr)) {
+                Some(data) => data,
+                None => self.cartridge.as_ref().map_or(0xff, |c| c.read_rom(addr)),
+            },
+            0x8000..=0x9fff => self.vram[(addr - 0x8000) as usize],
+            0xa000..=0xbfff => self.cartridge.as_ref().map_or(0xff, |c| c.read_ram(addr)),
+            0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize],
+            0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize],
+            0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize],
+            // the unusable area reads as 0 on DMG.
+            0xfea0..=0xfeff => 0x00,
+            0xff00..=0xff7f => self.read_io(addr),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize],
+            interrupt::IE_ADDR => self.interrupt_enable,
+        }
     }
+}
 
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+impl Bus for Mmu {
+    // DMA has the external and video buses to itself, the CPU sees 0xff there until it's done.
+    fn read_byte(&self, addr: u16) -> u8 {
+        if (self.dma.active() && addr < 0xff00) || self.video_locked(addr) {
+            return 0xff;
+        }
+        self.read(addr)
     }
 
-    pub fn write_byte(&mut self, addr: u16, data: u8) {
+    fn write_byte(&mut self, addr: u16, data: u8) {
+        if (self.dma.active() && addr < 0xff00) || self.video_locked(addr) {
+            return;
+        }
         match addr {
-            _ => self.memory[addr as usize] = data,
+            0x0000..=0x7fff => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_rom(addr, data);
+                }
+            }
+            0x8000..=0x9fff => self.vram[(addr - 0x8000) as usize] = data,
+            0xa000..=0xbfff => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_ram(addr, data);
+                }
+            }
+            0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize] = data,
+            0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize] = data,
+            0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize] = data,
+            0xfea0..=0xfeff => {}
+            0xff00..=0xff7f => self.write_io(addr, data),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize] = data,
+            interrupt::IE_ADDR => self.interrupt_enable = data,
+        }
+    }
+
+    fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.mask();
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.mask();
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1f
+    }
+
+    fn joypad_line_low(&self) -> bool {
+        self.joypad.line_low()
+    }
+
+    fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+    }
+
+    fn tick(&mut self) {
+        if let Some((source, offset)) = self.dma.tick() {
+            // sources past 0xdfff read through the echo of WRAM.
+            let source = if source >= 0xe000 {
+                source - 0x2000
+            } else {
+                source
+            };
+            self.oam[offset as usize] = self.read(source);
+        }
+        if self.timer.tick() {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if self.serial.tick() {
+            self.request_interrupt(Interrupt::Serial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boot::DMG_BOOT_ROM_SIZE;
+    use crate::cartridge;
+
+    fn mmu_with_cartridge(rom: Vec<u8>) -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+        mmu
+    }
+
+    #[test]
+    fn test_rom_is_read_only() {
+        let mut mmu = mmu_with_cartridge(cartridge::test_rom(0x00, 0x00, 0x00));
+        let data = mmu.read_byte(0x0150);
+        mmu.write_byte(0x0150, !data);
+        assert_eq!(mmu.read_byte(0x0150), data);
+    }
+
+    #[test]
+    fn test_boot_rom_unmaps_on_write() {
+        let boot_rom = BootRom::from_bytes(&[0x31; DMG_BOOT_ROM_SIZE]).unwrap();
+        let mut mmu = Mmu::with_boot_rom(boot_rom);
+        let mut rom = cartridge::test_rom(0x00, 0x00, 0x00);
+        rom[0x0000] = 0x42;
+        rom[0x0100] = 0x24;
+        mmu.insert_cartridge(Cartridge::from_bytes(rom).unwrap());
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+        assert_eq!(mmu.read_byte(0x0100), 0x24);
+
+        // writing zero leaves the boot ROM mapped.
+        mmu.write_byte(0xff50, 0x00);
+        assert_eq!(mmu.read_byte(0x0000), 0x31);
+
+        mmu.write_byte(0xff50, 0x01);
+        assert_eq!(mmu.read_byte(0x0000), 0x42);
+        assert_eq!(mmu.read_byte(0xff50), 0xff);
+    }
+
+    #[test]
+    fn test_skip_boot_io() {
+        let mmu = Mmu::skip_boot(Model::Dmg);
+        assert_eq!(mmu.read_byte(0xff40), 0x91);
+        assert_eq!(mmu.read_byte(0xff0f), 0xe1);
+        assert_eq!(mmu.read_byte(0xff47), 0xfc);
+        assert_eq!(mmu.read_byte(0xff44), 0x00);
+        assert_eq!(mmu.read_byte(0xff04), 0xab);
+        assert_eq!(mmu.read_byte(0xff46), 0xff);
+        assert_eq!(mmu.read_byte(0xff4d), 0xff);
+
+        let mmu = Mmu::skip_boot(Model::Cgb);
+        assert_eq!(mmu.read_byte(0xff40), 0x91);
+        assert_eq!(mmu.read_byte(0xff04), 0x00);
+        assert_eq!(mmu.read_byte(0xff46), 0x00);
+        assert_eq!(mmu.read_byte(0xff4d), 0x7e);
+    }
+
+    #[test]
+    fn test_cartridge_ram() {
+        let mmu = Mmu::new();
+        assert_eq!(mmu.read_byte(0x0000), 0xff);
+        assert_eq!(mmu.read_byte(0xa000), 0xff);
+
+        let mut mmu = mmu_with_cartridge(cartridge::test_rom(0x08, 0x00, 0x02));
+        mmu.write_byte(0xbfff, 0x42);
+        assert_eq!(mmu.read_byte(0xbfff), 0x42);
+    }
+
+    #[test]
+    fn test_echo_ram_mirrors_wram() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xc123, 0x42);
+        assert_eq!(mmu.read_byte(0xe123), 0x42);
+        mmu.write_byte(0xfdff, 0x24);
+        assert_eq!(mmu.read_byte(0xddff), 0x24);
+    }
+
+    #[test]
+    fn test_unmapped_reads() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xfea0, 0x42);
+        assert_eq!(mmu.read_byte(0xfea0), 0x00);
+        mmu.write_byte(0xff07, 0x00);
+        assert_eq!(mmu.read_byte(0xff07), 0xf8);
+        mmu.write_byte(0xff03, 0x00);
+        assert_eq!(mmu.read_byte(0xff03), 0xff);
+    }
+
+    #[test]
+    fn test_stat_and_ly_are_read_only() {
+        let mut mmu = Mmu::new();
+        mmu.set_ly(0x10);
+        mmu.set_stat(0x03);
+        mmu.write_byte(0xff44, 0x00);
+        mmu.write_byte(0xff41, 0xff);
+        assert_eq!(mmu.read_byte(0xff44), 0x10);
+        assert_eq!(mmu.read_byte(0xff41), 0xfb);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut mmu = Mmu::new();
+        for i in 0..0xa0 {
+            mmu.write_byte(0xc100 + i, i as u8);
+        }
+        mmu.write_byte(0xff80, 0x42);
+        mmu.write_byte(0xff46, 0xc1);
+        mmu.tick();
+
+        // only HRAM and the registers are reachable while the transfer runs.
+        assert_eq!(mmu.read_byte(0xc100), 0xff);
+        mmu.write_byte(0xc100, 0x24);
+        assert_eq!(mmu.read_byte(0xff80), 0x42);
+        assert_eq!(mmu.read_byte(0xff46), 0xc1);
+
+        for _ in 0..160 {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read_byte(0xc100), 0x00);
+        assert_eq!(mmu.read_byte(0xfe00), 0x00);
+        assert_eq!(mmu.read_byte(0xfe9f), 0x9f);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xff05, 0xff);
+        mmu.write_byte(0xff07, 0x05);
+        for _ in 0..4 {
+            mmu.tick();
         }
+        assert_eq!(mmu.read_byte(0xff0f) & 0x04, 0);
+        mmu.tick();
+        assert_eq!(mmu.read_byte(0xff0f) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn test_joypad() {
+        let mut mmu = Mmu::skip_boot(Model::Dmg);
+        assert_eq!(mmu.read_byte(0xff00), 0xcf);
+        mmu.write_byte(0xff0f, 0x00);
+
+        mmu.write_byte(0xff00, 0x10);
+        mmu.press(Button::Start);
+        assert_eq!(mmu.read_byte(0xff00), 0xd7);
+        assert_eq!(mmu.read_byte(0xff0f) & 0x10, 0x10);
+
+        mmu.release(Button::Start);
+        assert_eq!(mmu.read_byte(0xff00), 0xdf);
+    }
+
+    #[test]
+    fn test_key1() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xff4d, 0x01);
+        assert_eq!(mmu.read_byte(0xff4d), 0xff);
+
+        let mut mmu = Mmu::with_model(Model::Cgb);
+        mmu.write_byte(0xff4d, 0x81);
+        assert_eq!(mmu.read_byte(0xff4d), 0x7f);
+        mmu.switch_speed();
+        assert_eq!(mmu.read_byte(0xff4d), 0xfe);
     }
 }
@@ -0,0 +1,8 @@
+pub const KEY1_ADDR: u16 = 0xff4d; // CGB speed switch
+
+// hardware revisions the emulator can behave as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg, // original Game Boy
+    Cgb, // Game Boy Color
+}
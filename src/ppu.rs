@@ -1,10 +1,48 @@
+use crate::bus::Bus;
+use crate::interrupt::Interrupt;
 use crate::mmu;
 use crate::utils;
 
+const LCDC_ADDR: u16 = 0xff40;
+const STAT_ADDR: u16 = 0xff41;
+const SCY_ADDR: u16 = 0xff42;
+const SCX_ADDR: u16 = 0xff43;
+const LYC_ADDR: u16 = 0xff45;
+const BGP_ADDR: u16 = 0xff47;
+const OBP0_ADDR: u16 = 0xff48;
+const OBP1_ADDR: u16 = 0xff49;
+const WY_ADDR: u16 = 0xff4a;
+const WX_ADDR: u16 = 0xff4b; // the window's left edge plus 7
+
 const SCANLINE_TICKS: u16 = 456;
 const SCREEN_LINES: u8 = 144;
 const SCANLINE_PIXELS: u8 = 160;
-const OAM_SEARCH_TICKS: u16 = 40;
+const OAM_SEARCH_TICKS: u16 = 80;
+const OAM_ADDR: u16 = 0xfe00;
+const OAM_SPRITES: u16 = 40;
+const SPRITES_PER_LINE: usize = 10;
+const FRAME_PIXELS: usize = SCREEN_LINES as usize * SCANLINE_PIXELS as usize;
+
+const LCDC_BG_ENABLE: u8 = 0x01; // background drawn as colour 0 when clear
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_SIZE: u8 = 0x04; // 8x16 sprites instead of 8x8
+const LCDC_BG_MAP: u8 = 0x08; // background map at 0x9c00 instead of 0x9800
+const LCDC_TILE_DATA: u8 = 0x10; // tiles at 0x8000 by unsigned id instead of 0x9000 by signed id
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_MAP: u8 = 0x40; // window map at 0x9c00 instead of 0x9800
+const LCDC_ENABLE: u8 = 0x80;
+
+const ATTR_BEHIND_BG: u8 = 0x80; // only shows over background colour 0
+const ATTR_Y_FLIP: u8 = 0x40;
+const ATTR_X_FLIP: u8 = 0x20;
+const ATTR_OBP1: u8 = 0x10;
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    colour: u8,
+    obp1: bool,
+    behind_bg: bool,
+}
 
 enum PpuState {
     OamSearch,     // Object Attribute Memory
@@ -13,13 +51,42 @@ enum PpuState {
     VBlank,        // Time to next frame
 }
 
+impl PpuState {
+    // mode number reported in the low two bits of STAT.
+    fn mode(&self) -> u8 {
+        match self {
+            PpuState::HBlank => 0,
+            PpuState::VBlank => 1,
+            PpuState::OamSearch => 2,
+            PpuState::PixelTransfer => 3,
+        }
+    }
+}
+
 pub struct Ppu {
     screen: Screen,
-    ticks: u16,      // keeps track of timing for various states
-    state: PpuState, // state of the PPU FSM
-    ly: u8,          // current line on screen
-    x: u8,           // current pixel on line
+    ticks: u16,         // keeps track of timing for various states
+    state: PpuState,    // state of the PPU FSM
+    ly: u8,             // current line on screen
+    x: u8,              // current pixel on line
+    discard: u8,        // pixels still to drop for fine horizontal scroll
+    window_line: u8,    // window row to draw next, only advances on lines that showed the window
+    wy_triggered: bool, // LY matched WY at some point this frame
+    in_window: bool,    // fetching the window for the rest of the line
+    window_spill: bool, // WX was 166 last line, so this one starts in the window
     fetcher: Fetcher,
+    stat_line: bool, // STAT interrupt fires on the rising edge of this
+    sprite_line: [Option<SpritePixel>; SCANLINE_PIXELS as usize], // sprites on the current line
+    frame: Box<[u8; FRAME_PIXELS]>, // shades of the last frame after the palettes, row by row
+    enabled: bool,   // LCDC bit 7 as of the last tick
+    first_line: bool, // line 0 after switching on has no OAM search
+    skip_frame: bool, // the frame after switching on isn't shown
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ppu {
@@ -30,44 +97,124 @@ impl Ppu {
             state: PpuState::OamSearch,
             ly: 0,
             x: 0,
+            discard: 0,
+            window_line: 0,
+            wy_triggered: false,
+            in_window: false,
+            window_spill: false,
             fetcher: Fetcher::new(),
+            stat_line: false,
+            sprite_line: [None; SCANLINE_PIXELS as usize],
+            frame: Box::new([0; FRAME_PIXELS]),
+            enabled: true,
+            first_line: false,
+            skip_frame: false,
+        }
+    }
+
+    // the boot ROM hands over on the last line of VBlank. LY already reads 0 there, the next line
+    // starts a fresh frame.
+    pub fn skip_boot() -> Self {
+        Ppu {
+            state: PpuState::VBlank,
+            ly: SCREEN_LINES + 9,
+            ..Self::new()
         }
     }
 
+    pub fn frame(&self) -> &[u8; FRAME_PIXELS] {
+        &self.frame
+    }
+
     pub fn tick(&mut self, mmu: &mut mmu::Mmu) {
+        let lcdc = mmu.read_byte(LCDC_ADDR);
+        if lcdc & LCDC_ENABLE == 0 {
+            if self.enabled {
+                self.switch_off(mmu);
+            }
+            return;
+        }
+        if !self.enabled {
+            self.switch_on();
+        }
+
         self.ticks += 1;
 
         match self.state {
             PpuState::OamSearch => {
                 if self.ticks == OAM_SEARCH_TICKS {
                     self.x = 0;
-                    let tile_line = self.ly % 8;
-                    let tile_map_row_addr = 0x9800 + ((self.ly / 8) as u16 * 32);
-                    self.fetcher.start(tile_map_row_addr, tile_line);
+                    let scx = mmu.read_byte(SCX_ADDR);
+                    let y = self.ly.wrapping_add(mmu.read_byte(SCY_ADDR));
+                    self.discard = scx % 8;
+                    self.first_line = false;
+                    self.in_window = false;
+                    self.search_oam(mmu, lcdc);
+                    if self.ly == mmu.read_byte(WY_ADDR) {
+                        self.wy_triggered = true;
+                    }
+                    let map = if lcdc & LCDC_BG_MAP != 0 {
+                        0x9c00
+                    } else {
+                        0x9800
+                    };
+                    let tile_map_row_addr = map + ((y / 8) as u16 * 32);
+                    self.fetcher.start(tile_map_row_addr, scx / 8, y % 8);
                     self.state = PpuState::PixelTransfer;
                 }
             }
             PpuState::PixelTransfer => {
-                self.fetcher.tick(mmu);
-                if self.fetcher.rb.size() <= 8 {
-                    return;
+                if !self.in_window && lcdc & LCDC_WINDOW_ENABLE != 0 && self.wy_triggered {
+                    let wx = mmu.read_byte(WX_ADDR);
+                    if self.x == 0 && self.window_spill {
+                        self.start_window(lcdc, 7);
+                    } else if (self.x == 0 && wx < 7) || (wx < 166 && self.x + 7 == wx) {
+                        self.start_window(lcdc, wx);
+                    }
                 }
-
-                let pixel = self.fetcher.rb.get();
-                self.screen.write(pixel);
-                self.x += 1;
-                if self.x == SCANLINE_PIXELS {
-                    self.screen.h_blank();
-                    self.state = PpuState::HBlank;
+                self.fetcher.tick(mmu);
+                // dropping the pixels scrolled off the left edge costs a dot each.
+                if self.fetcher.rb.size() > 8 && self.discard > 0 {
+                    self.fetcher.rb.get();
+                    self.discard -= 1;
+                } else if self.fetcher.rb.size() > 8 {
+                    let mut bg = self.fetcher.rb.get();
+                    if lcdc & LCDC_BG_ENABLE == 0 {
+                        bg = 0;
+                    }
+                    let pixel = self.mix(mmu, lcdc, bg);
+                    if !self.skip_frame {
+                        self.frame[self.ly as usize * SCANLINE_PIXELS as usize + self.x as usize] =
+                            pixel;
+                        self.screen.write(pixel);
+                    }
+                    self.x += 1;
+                    if self.x == SCANLINE_PIXELS {
+                        if !self.skip_frame {
+                            self.screen.h_blank();
+                        }
+                        if self.in_window {
+                            self.window_line += 1;
+                        }
+                        // WX=166 doesn't show on this line but covers all of the next one.
+                        self.window_spill = lcdc & LCDC_WINDOW_ENABLE != 0
+                            && self.wy_triggered
+                            && mmu.read_byte(WX_ADDR) == 166;
+                        self.state = PpuState::HBlank;
+                    }
                 }
             }
             PpuState::HBlank => {
                 if self.ticks == SCANLINE_TICKS {
                     self.ticks = 0;
                     self.ly += 1;
-                    mmu.write_byte(0xff44, self.ly);
+                    mmu.set_ly(self.ly);
                     if self.ly == SCREEN_LINES {
-                        self.screen.v_blank();
+                        if !self.skip_frame {
+                            self.screen.v_blank();
+                        }
+                        self.skip_frame = false;
+                        mmu.request_interrupt(Interrupt::VBlank);
                         self.state = PpuState::VBlank;
                     } else {
                         self.state = PpuState::OamSearch;
@@ -78,14 +225,145 @@ impl Ppu {
                 if self.ticks == SCANLINE_TICKS {
                     self.ticks = 0;
                     self.ly += 1;
-                    mmu.write_byte(0xff44, self.ly);
-                    if self.ly == (SCREEN_LINES + 9) {
+                    if self.ly == (SCREEN_LINES + 10) {
                         self.ly = 0;
+                        self.window_line = 0;
+                        self.wy_triggered = false;
+                        self.window_spill = false;
                         self.state = PpuState::OamSearch;
                     }
+                    mmu.set_ly(self.ly);
                 }
             }
         }
+
+        self.update_stat(mmu);
+    }
+
+    // with the LCD off the PPU sits at the start of line 0 in mode 0, and the CPU has VRAM and
+    // OAM to itself. the screen goes blank.
+    fn switch_off(&mut self, mmu: &mut mmu::Mmu) {
+        self.enabled = false;
+        self.ticks = 0;
+        self.ly = 0;
+        self.state = PpuState::OamSearch;
+        self.stat_line = false;
+        self.window_line = 0;
+        self.wy_triggered = false;
+        self.window_spill = false;
+        self.frame.fill(0);
+        mmu.set_ly(0);
+        let stat = mmu.read_byte(STAT_ADDR);
+        mmu.set_stat(stat & 0x7c);
+    }
+
+    // picks the first ten sprites in OAM that cover this line, offscreen ones included, and draws
+    // them into the line buffer. on DMG the sprite with the lower X wins where they overlap, then
+    // the one earlier in OAM, but a transparent pixel lets the next one through.
+    fn search_oam(&mut self, mmu: &mmu::Mmu, lcdc: u8) {
+        self.sprite_line = [None; SCANLINE_PIXELS as usize];
+        let height = if lcdc & LCDC_OBJ_SIZE != 0 { 16 } else { 8 };
+        let line = self.ly as u16 + 16;
+        let mut sprites: Vec<(u8, u16)> = (0..OAM_SPRITES)
+            .map(|i| OAM_ADDR + i * 4)
+            .filter(|&addr| {
+                let y = mmu.read_video(addr) as u16;
+                line >= y && line < y + height
+            })
+            .take(SPRITES_PER_LINE)
+            .map(|addr| (mmu.read_video(addr + 1), addr))
+            .collect();
+        sprites.sort();
+
+        for (x, addr) in sprites {
+            let attrs = mmu.read_video(addr + 3);
+            let mut row = line - mmu.read_video(addr) as u16;
+            if attrs & ATTR_Y_FLIP != 0 {
+                row = height - 1 - row;
+            }
+            // 8x16 sprites are a pair of tiles, the low bit of the tile number is ignored.
+            let mut tile = mmu.read_video(addr + 2);
+            if height == 16 {
+                tile &= 0xfe;
+            }
+            let data_addr = 0x8000 + tile as u16 * 16 + row * 2;
+            let low = mmu.read_video(data_addr);
+            let high = mmu.read_video(data_addr + 1);
+
+            for i in 0..8 {
+                let screen_x = x as usize + i;
+                if !(8..SCANLINE_PIXELS as usize + 8).contains(&screen_x) {
+                    continue;
+                }
+                let bit = if attrs & ATTR_X_FLIP != 0 { i } else { 7 - i };
+                let colour = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                let slot = &mut self.sprite_line[screen_x - 8];
+                if colour != 0 && slot.is_none() {
+                    *slot = Some(SpritePixel {
+                        colour,
+                        obp1: attrs & ATTR_OBP1 != 0,
+                        behind_bg: attrs & ATTR_BEHIND_BG != 0,
+                    });
+                }
+            }
+        }
+    }
+
+    // puts the sprite over the background colour unless it's behind a non-zero one, and turns
+    // the winner into a shade through its palette.
+    fn mix(&self, mmu: &mmu::Mmu, lcdc: u8, bg: u8) -> u8 {
+        let sprite = self.sprite_line[self.x as usize].filter(|_| lcdc & LCDC_OBJ_ENABLE != 0);
+        let (palette, colour) = match sprite {
+            Some(sprite) if !sprite.behind_bg || bg == 0 => {
+                let palette = if sprite.obp1 { OBP1_ADDR } else { OBP0_ADDR };
+                (palette, sprite.colour)
+            }
+            _ => (BGP_ADDR, bg),
+        };
+        (mmu.read_byte(palette) >> (colour * 2)) & 0x03
+    }
+
+    // swaps the background fetch for the window's, which has its own map and isn't scrolled. with
+    // WX below 7 the window's first pixels are off the left edge.
+    fn start_window(&mut self, lcdc: u8, wx: u8) {
+        let map = if lcdc & LCDC_WINDOW_MAP != 0 {
+            0x9c00
+        } else {
+            0x9800
+        };
+        let row_addr = map + (self.window_line / 8) as u16 * 32;
+        self.fetcher.start(row_addr, 0, self.window_line % 8);
+        self.discard = 7u8.saturating_sub(wx);
+        self.in_window = true;
+    }
+
+    fn switch_on(&mut self) {
+        self.enabled = true;
+        self.first_line = true;
+        self.skip_frame = true;
+    }
+
+    // keeps the mode and LY=LYC bits of STAT current and requests a STAT interrupt when any of
+    // the enabled sources becomes active.
+    fn update_stat(&mut self, mmu: &mut mmu::Mmu) {
+        // STAT says mode 0 through the OAM search time of the first line after switching on.
+        let mode = if self.first_line {
+            0
+        } else {
+            self.state.mode()
+        };
+        let coincidence = self.ly == mmu.read_byte(LYC_ADDR);
+        let stat = mmu.read_byte(STAT_ADDR);
+        mmu.set_stat((stat & 0x78) | ((coincidence as u8) << 2) | mode);
+
+        let line = (stat & 0x40 != 0 && coincidence)
+            || (stat & 0x20 != 0 && mode == 2)
+            || (stat & 0x10 != 0 && mode == 1)
+            || (stat & 0x08 != 0 && mode == 0);
+        if line && !self.stat_line {
+            mmu.request_interrupt(Interrupt::Stat);
+        }
+        self.stat_line = line;
     }
 }
 
@@ -99,7 +377,8 @@ enum FetcherState {
 struct Fetcher {
     state: FetcherState,
     ticks: u8,
-    tile_index: u8,
+    tile_index: u8, // tiles fetched so far this line
+    tile_col: u8,   // map column of the first tile
     tile_line: u8,
     tile_id: u8,
     map_addr: u16,
@@ -113,6 +392,7 @@ impl Fetcher {
             state: FetcherState::ReadTileId,
             ticks: 0,
             tile_index: 0,
+            tile_col: 0,
             map_addr: 0,
             tile_id: 0,
             tile_line: 0,
@@ -130,13 +410,13 @@ impl Fetcher {
 
         match self.state {
             FetcherState::ReadTileId => {
-                self.tile_id = mmu.read_byte(self.map_addr + self.tile_index as u16);
+                // the map is 32 tiles wide and wraps around.
+                let col = self.tile_col.wrapping_add(self.tile_index) % 32;
+                self.tile_id = mmu.read_video(self.map_addr + col as u16);
                 self.state = FetcherState::ReadTileData0;
             }
             FetcherState::ReadTileData0 => {
-                let offset = 0x8000 + self.tile_id as u16;
-                let addr = offset + ((self.tile_line as u16) * 2);
-                let data = mmu.read_byte(addr);
+                let data = mmu.read_video(self.tile_row_addr(mmu));
                 for i in 0..8 {
                     self.tile_data[i] = (data >> i) & 1;
                 }
@@ -144,11 +424,9 @@ impl Fetcher {
                 self.state = FetcherState::ReadTileData1;
             }
             FetcherState::ReadTileData1 => {
-                let offset = 0x8000 + self.tile_id as u16;
-                let addr = offset + ((self.tile_line as u16) * 2);
-                let data = mmu.read_byte(addr);
+                let data = mmu.read_video(self.tile_row_addr(mmu) + 1);
                 for i in 0..8 {
-                    self.tile_data[i] = ((data >> i) & 1) << 1;
+                    self.tile_data[i] |= ((data >> i) & 1) << 1;
                 }
                 self.state = FetcherState::PushToFifo;
             }
@@ -164,8 +442,20 @@ impl Fetcher {
         }
     }
 
-    pub fn start(&mut self, map_addr: u16, tile_line: u8) {
+    // tiles are 16 bytes, two bitplanes per row. the low plane comes first and bit 7 is the
+    // leftmost pixel.
+    fn tile_row_addr(&self, mmu: &mmu::Mmu) -> u16 {
+        let tile = if mmu.read_byte(LCDC_ADDR) & LCDC_TILE_DATA != 0 {
+            0x8000 + self.tile_id as u16 * 16
+        } else {
+            0x9000u16.wrapping_add((self.tile_id as i8 as i16 * 16) as u16)
+        };
+        tile + self.tile_line as u16 * 2
+    }
+
+    pub fn start(&mut self, map_addr: u16, tile_col: u8, tile_line: u8) {
         self.tile_index = 0;
+        self.tile_col = tile_col;
         self.map_addr = map_addr;
         self.tile_line = tile_line;
         self.state = FetcherState::ReadTileId;
@@ -177,6 +467,12 @@ pub struct Screen {
     palette: [char; 4],
 }
 
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Screen {
     pub fn new() -> Self {
         Screen {
@@ -189,7 +485,7 @@ impl Screen {
     }
 
     pub fn h_blank(&self) {
-        print!("\n");
+        println!();
     }
 
     pub fn v_blank(&self) {
@@ -197,3 +493,321 @@ impl Screen {
         //print!("\x1B[2J\x1B[1;1H");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LCD set up with the identity background palette, so shades match colour numbers.
+    fn lcd(lcdc: u8) -> mmu::Mmu {
+        let mut mmu = mmu::Mmu::new();
+        mmu.write_byte(LCDC_ADDR, lcdc);
+        mmu.write_byte(BGP_ADDR, 0xe4);
+        mmu
+    }
+
+    fn run_line(ppu: &mut Ppu, mmu: &mut mmu::Mmu) {
+        for _ in 0..SCANLINE_TICKS {
+            ppu.tick(mmu);
+        }
+    }
+
+    // first row of a tile drawn as 3, 2, 1, 0, 3, 2, 1, 0.
+    fn write_tile_row(mmu: &mut mmu::Mmu, addr: u16) {
+        mmu.write_byte(addr, 0xaa);
+        mmu.write_byte(addr + 1, 0xcc);
+    }
+
+    #[test]
+    fn test_unsigned_tile_data() {
+        let mut mmu = lcd(0x91);
+        mmu.write_byte(0x9800, 0x01);
+        write_tile_row(&mut mmu, 0x8010);
+
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(ppu.frame()[..8], [3, 2, 1, 0, 3, 2, 1, 0]);
+        assert_eq!(ppu.frame()[8..16], [0; 8]);
+    }
+
+    #[test]
+    fn test_signed_tile_data_and_high_map() {
+        let mut mmu = lcd(0x89);
+        mmu.write_byte(0x9c00, 0x80);
+        mmu.write_byte(0x9c01, 0x01);
+        write_tile_row(&mut mmu, 0x8800);
+        write_tile_row(&mut mmu, 0x9010);
+
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(
+            ppu.frame()[..16],
+            [3, 2, 1, 0, 3, 2, 1, 0, 3, 2, 1, 0, 3, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_bg_disable() {
+        let mut mmu = lcd(0x90);
+        mmu.write_byte(0x9800, 0x01);
+        write_tile_row(&mut mmu, 0x8010);
+
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(ppu.frame()[..8], [0; 8]);
+    }
+
+    #[test]
+    fn test_vram_locked_while_drawing() {
+        let mut mmu = lcd(0x91);
+        mmu.write_byte(0x8000, 0x42);
+
+        let mut ppu = Ppu::new();
+        for _ in 0..OAM_SEARCH_TICKS {
+            ppu.tick(&mut mmu);
+        }
+        assert_eq!(mmu.read_byte(STAT_ADDR) & 0x03, 3);
+        assert_eq!(mmu.read_byte(0x8000), 0xff);
+        assert_eq!(mmu.read_byte(0xfe00), 0xff);
+    }
+
+    #[test]
+    fn test_lcd_off_and_on() {
+        let mut mmu = lcd(0x91);
+        mmu.write_byte(0x9800, 0x01);
+        write_tile_row(&mut mmu, 0x8010);
+
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        for _ in 0..OAM_SEARCH_TICKS {
+            ppu.tick(&mut mmu);
+        }
+        assert_eq!(mmu.read_byte(0xff44), 1);
+
+        mmu.write_byte(LCDC_ADDR, 0x11);
+        ppu.tick(&mut mmu);
+        assert_eq!(mmu.read_byte(0xff44), 0);
+        assert_eq!(mmu.read_byte(STAT_ADDR) & 0x03, 0);
+        assert_eq!(ppu.frame()[..8], [0; 8]);
+        mmu.write_byte(0x8010, 0xaa);
+        assert_eq!(mmu.read_byte(0x8010), 0xaa);
+
+        // the first frame after switching back on stays blank, and line 0 reports mode 0 for
+        // the time OAM search would have taken.
+        mmu.write_byte(LCDC_ADDR, 0x91);
+        ppu.tick(&mut mmu);
+        assert_eq!(mmu.read_byte(STAT_ADDR) & 0x03, 0);
+        for _ in 1..SCANLINE_TICKS as u32 * SCREEN_LINES as u32 {
+            ppu.tick(&mut mmu);
+        }
+        assert_eq!(ppu.frame()[..8], [0; 8]);
+
+        for _ in 0..SCANLINE_TICKS as u32 * 11 {
+            ppu.tick(&mut mmu);
+        }
+        assert_eq!(ppu.frame()[..8], [3, 2, 1, 0, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut mmu = lcd(0x91);
+        // tile 1 at map row 1, column 31.
+        mmu.write_byte(0x9800 + 32 + 31, 0x01);
+        write_tile_row(&mut mmu, 0x8010 + 4);
+        mmu.write_byte(SCY_ADDR, 10);
+        mmu.write_byte(SCX_ADDR, 0xfb);
+
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(ppu.frame()[..5], [0, 3, 2, 1, 0]);
+        assert_eq!(ppu.frame()[5..13], [0; 8]);
+    }
+
+    #[test]
+    fn test_fine_scroll_lengthens_mode_3() {
+        let mode_3_ticks = |scx| {
+            let mut mmu = lcd(0x91);
+            mmu.write_byte(SCX_ADDR, scx);
+            let mut ppu = Ppu::new();
+            let mut ticks = 0;
+            loop {
+                ppu.tick(&mut mmu);
+                ticks += 1;
+                if mmu.read_byte(STAT_ADDR) & 0x03 == 0 {
+                    return ticks;
+                }
+            }
+        };
+        assert_eq!(mode_3_ticks(5) - mode_3_ticks(0), 5);
+        assert_eq!(mode_3_ticks(8), mode_3_ticks(0));
+    }
+
+    // background of solid colour 3 with the window map at 0x9c00 showing tile 1 in its first
+    // column. tile 1 has a different pattern on each of its first two rows.
+    fn window_mmu(wx: u8, wy: u8) -> mmu::Mmu {
+        let mut mmu = lcd(0xf1);
+        for i in 0..0x400 {
+            mmu.write_byte(0x9800 + i, 0x02);
+        }
+        for i in 0..16 {
+            mmu.write_byte(0x8020 + i, 0xff);
+        }
+        mmu.write_byte(0x9c00, 0x01);
+        mmu.write_byte(0x9c20, 0x01);
+        write_tile_row(&mut mmu, 0x8010);
+        mmu.write_byte(0x8012, 0x0f);
+        mmu.write_byte(0x8013, 0x0f);
+        mmu.write_byte(WX_ADDR, wx);
+        mmu.write_byte(WY_ADDR, wy);
+        mmu
+    }
+
+    fn line(ppu: &Ppu, ly: usize) -> &[u8] {
+        &ppu.frame()[ly * SCANLINE_PIXELS as usize..(ly + 1) * SCANLINE_PIXELS as usize]
+    }
+
+    #[test]
+    fn test_window() {
+        let mut mmu = window_mmu(23, 1);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0)[..32], [3; 32]);
+
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 1)[..16], [3; 16]);
+        assert_eq!(line(&ppu, 1)[16..24], [3, 2, 1, 0, 3, 2, 1, 0]);
+        assert_eq!(line(&ppu, 1)[24..32], [0; 8]);
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        let mut mmu = window_mmu(7, 0);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        mmu.write_byte(LCDC_ADDR, 0xd1);
+        run_line(&mut ppu, &mut mmu);
+        mmu.write_byte(LCDC_ADDR, 0xf1);
+        run_line(&mut ppu, &mut mmu);
+
+        assert_eq!(line(&ppu, 1)[..8], [3; 8]);
+        // the window picks up on its second row, not the third.
+        assert_eq!(line(&ppu, 2)[..8], [0, 0, 0, 0, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_window_left_edge_cases() {
+        let mut mmu = window_mmu(4, 0);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0)[..6], [0, 3, 2, 1, 0, 0]);
+
+        let mut mmu = window_mmu(166, 0);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0), [3; 160]);
+        assert_eq!(line(&ppu, 1)[..8], [3, 2, 1, 0, 3, 2, 1, 0]);
+    }
+
+    // blank background in shade 1, sprites through the identity palette in OBP0 and reversed in
+    // OBP1. tile 1 is the usual 3, 2, 1, 0 pattern, tile 2 is solid colour 3 and tile 3 is blank.
+    fn sprite_mmu(lcdc: u8) -> mmu::Mmu {
+        let mut mmu = lcd(lcdc);
+        mmu.write_byte(BGP_ADDR, 0xe5);
+        mmu.write_byte(OBP0_ADDR, 0xe4);
+        mmu.write_byte(OBP1_ADDR, 0x1b);
+        for row in 0..8 {
+            write_tile_row(&mut mmu, 0x8010 + row * 2);
+            mmu.write_byte(0x8020 + row * 2, 0xff);
+            mmu.write_byte(0x8021 + row * 2, 0xff);
+        }
+        mmu
+    }
+
+    fn sprite(mmu: &mut mmu::Mmu, index: u16, y: u8, x: u8, tile: u8, attrs: u8) {
+        let addr = OAM_ADDR + index * 4;
+        for (i, data) in [y, x, tile, attrs].into_iter().enumerate() {
+            mmu.write_byte(addr + i as u16, data);
+        }
+    }
+
+    #[test]
+    fn test_sprites_flips_and_palettes() {
+        let mut mmu = sprite_mmu(0x93);
+        sprite(&mut mmu, 0, 16, 8, 1, 0x00);
+        sprite(&mut mmu, 1, 16, 16, 1, ATTR_X_FLIP | ATTR_OBP1);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        // colour 0 is transparent and shows the background.
+        assert_eq!(line(&ppu, 0)[..8], [3, 2, 1, 1, 3, 2, 1, 1]);
+        assert_eq!(line(&ppu, 0)[8..16], [1, 2, 1, 0, 1, 2, 1, 0]);
+        assert_eq!(line(&ppu, 0)[16..24], [1; 8]);
+
+        mmu.write_byte(LCDC_ADDR, 0x91);
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 1)[..16], [1; 16]);
+    }
+
+    #[test]
+    fn test_tall_sprites_and_y_flip() {
+        let mut mmu = sprite_mmu(0x97);
+        // the low bit of the tile number is ignored, tile 2 goes on top of tile 3.
+        sprite(&mut mmu, 0, 16, 8, 3, 0x00);
+        sprite(&mut mmu, 1, 16, 16, 3, ATTR_Y_FLIP);
+        let mut ppu = Ppu::new();
+        for _ in 0..9 {
+            run_line(&mut ppu, &mut mmu);
+        }
+        assert_eq!(
+            line(&ppu, 0)[..16],
+            [3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            line(&ppu, 7)[..16],
+            [3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            line(&ppu, 8)[..16],
+            [1, 1, 1, 1, 1, 1, 1, 1, 3, 3, 3, 3, 3, 3, 3, 3]
+        );
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut mmu = sprite_mmu(0x93);
+        // lower X wins, and its transparent pixels let the other one through.
+        sprite(&mut mmu, 0, 16, 12, 2, 0x00);
+        sprite(&mut mmu, 1, 16, 8, 1, ATTR_OBP1);
+        // at the same X the one earlier in OAM wins.
+        sprite(&mut mmu, 2, 16, 40, 2, ATTR_OBP1);
+        sprite(&mut mmu, 3, 16, 40, 1, 0x00);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0)[..12], [0, 1, 2, 1, 0, 1, 2, 3, 3, 3, 3, 3]);
+        assert_eq!(line(&ppu, 0)[32..40], [0; 8]);
+    }
+
+    #[test]
+    fn test_ten_sprites_per_line() {
+        let mut mmu = sprite_mmu(0x93);
+        // an offscreen sprite still uses up a slot.
+        sprite(&mut mmu, 0, 16, 0, 2, 0x00);
+        for i in 1..11 {
+            sprite(&mut mmu, i, 16, 8 * i as u8, 2, 0x00);
+        }
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0)[..72], [3; 72]);
+        assert_eq!(line(&ppu, 0)[72..80], [1; 8]);
+    }
+
+    #[test]
+    fn test_sprite_behind_background() {
+        let mut mmu = sprite_mmu(0x93);
+        mmu.write_byte(0x9800, 0x01);
+        sprite(&mut mmu, 0, 16, 8, 2, ATTR_BEHIND_BG);
+        let mut ppu = Ppu::new();
+        run_line(&mut ppu, &mut mmu);
+        assert_eq!(line(&ppu, 0)[..8], [3, 2, 1, 3, 3, 2, 1, 3]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+pub const SB_ADDR: u16 = 0xff01;
+pub const SC_ADDR: u16 = 0xff02;
+
+const CYCLES_PER_BIT: u16 = 128; // the internal clock runs at 8192 Hz
+
+// whatever is plugged into the other end of the link port.
+pub trait SerialLink {
+    // a transfer on our internal clock. sends our byte and returns the one shifted in.
+    fn exchange(&mut self, data: u8) -> u8;
+
+    // we're waiting on the other end's clock, with data ready to shift out.
+    fn listen(&mut self, _data: u8) {}
+
+    // a byte the other end clocked in while we were listening.
+    fn receive(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+// nothing plugged in. the input line floats high so every bit reads 1, and a transfer on the
+// external clock never finishes.
+pub struct Disconnected;
+
+impl SerialLink for Disconnected {
+    fn exchange(&mut self, _data: u8) -> u8 {
+        0xff
+    }
+}
+
+// collects every byte sent, as a disconnected port otherwise. blargg's test ROMs print their
+// results this way.
+pub struct Capture {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new(output: Rc<RefCell<Vec<u8>>>) -> Self {
+        Capture { output }
+    }
+}
+
+impl SerialLink for Capture {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.output.borrow_mut().push(data);
+        0xff
+    }
+}
+
+#[derive(Debug, Default)]
+struct LinkEnd {
+    listening: Option<u8>, // byte this end will shift out once the other end clocks
+    received: Option<u8>,
+}
+
+// one end of a cable between two emulator instances, made by `LinkCable::pair`.
+pub struct LinkCable {
+    ends: Rc<RefCell<[LinkEnd; 2]>>,
+    side: usize,
+}
+
+impl LinkCable {
+    pub fn pair() -> (LinkCable, LinkCable) {
+        let ends = Rc::new(RefCell::new([LinkEnd::default(), LinkEnd::default()]));
+        (
+            LinkCable {
+                ends: ends.clone(),
+                side: 0,
+            },
+            LinkCable { ends, side: 1 },
+        )
+    }
+}
+
+impl SerialLink for LinkCable {
+    // the other end only takes part if it was already listening, otherwise we clock in 1s.
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut ends = self.ends.borrow_mut();
+        let other = &mut ends[1 - self.side];
+        match other.listening.take() {
+            Some(theirs) => {
+                other.received = Some(data);
+                theirs
+            }
+            None => 0xff,
+        }
+    }
+
+    fn listen(&mut self, data: u8) {
+        self.ends.borrow_mut()[self.side].listening = Some(data);
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.ends.borrow_mut()[self.side].received.take()
+    }
+}
+
+// SB is shifted out MSB first while the other end's bits shift in. with the internal clock a bit
+// moves every 128 M-cycles, with the external clock the other end decides.
+pub struct Serial {
+    sb: u8,
+    sc: u8,       // bit 7 transfer in progress, bit 0 internal clock
+    incoming: u8, // bits still to shift in from the other end
+    bits: u8,     // bits shifted in the current transfer
+    cycles: u16,  // M-cycles into the current bit
+    link: Box<dyn SerialLink>,
+}
+
+impl fmt::Debug for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Serial")
+            .field("sb", &self.sb)
+            .field("sc", &self.sc)
+            .finish()
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            incoming: 0,
+            bits: 0,
+            cycles: 0,
+            link: Box::new(Disconnected),
+        }
+    }
+
+    pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    // advances one M-cycle, returning true when the serial interrupt should be requested.
+    pub fn tick(&mut self) -> bool {
+        if self.sc & 0x80 == 0 {
+            return false;
+        }
+        if self.sc & 0x01 == 0 {
+            return match self.link.receive() {
+                Some(data) => {
+                    self.sb = data;
+                    self.sc &= 0x7f;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        self.cycles += 1;
+        if self.cycles < CYCLES_PER_BIT {
+            return false;
+        }
+        self.cycles = 0;
+        self.sb = (self.sb << 1) | (self.incoming >> 7);
+        self.incoming <<= 1;
+        self.bits += 1;
+        if self.bits == 8 {
+            self.sc &= 0x7f;
+            return true;
+        }
+        false
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB_ADDR => self.sb,
+            SC_ADDR => 0x7e | self.sc,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            SB_ADDR => self.sb = data,
+            SC_ADDR => {
+                self.sc = data & 0x81;
+                if data & 0x80 == 0 {
+                    return;
+                }
+                if data & 0x01 != 0 {
+                    self.incoming = self.link.exchange(self.sb);
+                    self.bits = 0;
+                    self.cycles = 0;
+                } else {
+                    self.link.listen(self.sb);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Capture::new(output.clone())));
+        serial.write(SB_ADDR, 0x41);
+        serial.write(SC_ADDR, 0x81);
+        assert_eq!(*output.borrow(), b"A");
+
+        for _ in 0..CYCLES_PER_BIT {
+            assert!(!serial.tick());
+        }
+        assert_eq!(serial.read(SB_ADDR), 0x83);
+        for _ in 0..7 * CYCLES_PER_BIT - 1 {
+            assert!(!serial.tick());
+        }
+        assert!(serial.tick());
+        assert_eq!(serial.read(SB_ADDR), 0xff);
+        assert_eq!(serial.read(SC_ADDR), 0x7f);
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_the_other_end() {
+        let mut serial = Serial::new();
+        serial.write(SC_ADDR, 0x80);
+        for _ in 0..8 * CYCLES_PER_BIT {
+            assert!(!serial.tick());
+        }
+        assert_eq!(serial.read(SC_ADDR), 0xfe);
+    }
+
+    #[test]
+    fn test_link_cable() {
+        let (a, b) = LinkCable::pair();
+        let mut master = Serial::new();
+        let mut slave = Serial::new();
+        master.connect(Box::new(a));
+        slave.connect(Box::new(b));
+
+        slave.write(SB_ADDR, 0x24);
+        slave.write(SC_ADDR, 0x80);
+        master.write(SB_ADDR, 0x42);
+        master.write(SC_ADDR, 0x81);
+
+        assert!(slave.tick());
+        assert_eq!(slave.read(SB_ADDR), 0x42);
+        while !master.tick() {}
+        assert_eq!(master.read(SB_ADDR), 0x24);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::model::Model;
+
+pub const DIV_ADDR: u16 = 0xff04;
+pub const TIMA_ADDR: u16 = 0xff05;
+pub const TMA_ADDR: u16 = 0xff06;
+pub const TAC_ADDR: u16 = 0xff07;
+
+// bit of the system counter whose falling edge clocks TIMA, indexed by the low bits of TAC.
+const TAC_BITS: [u8; 4] = [9, 3, 5, 7];
+
+// where TIMA is after overflowing. it reads 0 for an M-cycle before TMA is copied in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reload {
+    None,
+    Pending,   // overflowed last cycle, TMA goes in on the next one
+    Reloading, // TMA was copied in this cycle, writes to TIMA are lost
+}
+
+// DIV is the top byte of a 16 bit counter running at the system clock. TIMA counts falling edges
+// of one of its bits, ANDed with the enable bit of TAC, which is why resetting DIV or changing
+// TAC can tick TIMA.
+#[derive(Debug)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload: Reload,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: Reload::None,
+        }
+    }
+
+    // the counter as the boot ROM leaves it. the CGB boot ROM runs for a time that depends on
+    // the cartridge, so there's no one value for it and the counter starts from 0.
+    pub fn skip_boot(model: Model) -> Self {
+        let counter = match model {
+            Model::Dmg => 0xabcc,
+            Model::Cgb => 0x0000,
+        };
+        Timer {
+            counter,
+            ..Self::new()
+        }
+    }
+
+    fn signal(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & (1 << TAC_BITS[(self.tac & 0x03) as usize]) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0 {
+            self.reload = Reload::Pending;
+        }
+    }
+
+    // advances one M-cycle, returning true when the timer interrupt should be requested.
+    pub fn tick(&mut self) -> bool {
+        let mut interrupt = false;
+        self.reload = match self.reload {
+            Reload::Pending => {
+                self.tima = self.tma;
+                interrupt = true;
+                Reload::Reloading
+            }
+            _ => Reload::None,
+        };
+
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+        interrupt
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV_ADDR => (self.counter >> 8) as u8,
+            TIMA_ADDR => self.tima,
+            TMA_ADDR => self.tma,
+            TAC_ADDR => 0xf8 | self.tac,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        let signal = self.signal();
+        match addr {
+            DIV_ADDR => self.counter = 0,
+            TIMA_ADDR => match self.reload {
+                // writing in the cycle after an overflow cancels the reload and the interrupt.
+                Reload::Pending => {
+                    self.tima = data;
+                    self.reload = Reload::None;
+                }
+                Reload::Reloading => {}
+                Reload::None => self.tima = data,
+            },
+            TMA_ADDR => {
+                self.tma = data;
+                if self.reload == Reload::Reloading {
+                    self.tima = data;
+                }
+            }
+            TAC_ADDR => self.tac = data & 0x07,
+            _ => {}
+        }
+        // resetting the counter or switching bits can pull the signal low, which counts as an edge.
+        if signal && !self.signal() {
+            self.increment_tima();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_counts_every_64_cycles() {
+        let mut timer = Timer::new();
+        for _ in 0..63 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(DIV_ADDR), 0);
+        timer.tick();
+        assert_eq!(timer.read(DIV_ADDR), 1);
+
+        timer.write(DIV_ADDR, 0x42);
+        assert_eq!(timer.read(DIV_ADDR), 0);
+    }
+
+    #[test]
+    fn test_overflow_reloads_a_cycle_late() {
+        let mut timer = Timer::new();
+        timer.write(TMA_ADDR, 0x80);
+        timer.write(TIMA_ADDR, 0xff);
+        timer.write(TAC_ADDR, 0x05); // every 4 M-cycles
+        for _ in 0..4 {
+            assert!(!timer.tick());
+        }
+        assert_eq!(timer.read(TIMA_ADDR), 0x00);
+        assert!(timer.tick());
+        assert_eq!(timer.read(TIMA_ADDR), 0x80);
+
+        // writes in the reload cycle lose to TMA.
+        timer.write(TIMA_ADDR, 0x10);
+        assert_eq!(timer.read(TIMA_ADDR), 0x80);
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = Timer::new();
+        timer.write(TIMA_ADDR, 0xff);
+        timer.write(TAC_ADDR, 0x05);
+        for _ in 0..4 {
+            timer.tick();
+        }
+        timer.write(TIMA_ADDR, 0x10);
+        assert!(!timer.tick());
+        assert_eq!(timer.read(TIMA_ADDR), 0x10);
+    }
+
+    #[test]
+    fn test_glitched_ticks() {
+        let mut timer = Timer::new();
+        timer.write(TAC_ADDR, 0x05);
+        timer.tick();
+        timer.tick(); // bit 3 set
+        timer.write(DIV_ADDR, 0);
+        assert_eq!(timer.read(TIMA_ADDR), 1);
+
+        timer.tick();
+        timer.tick();
+        timer.write(TAC_ADDR, 0x01); // disabling while the bit is high
+        assert_eq!(timer.read(TIMA_ADDR), 2);
+    }
+}
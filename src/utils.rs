@@ -9,7 +9,7 @@ pub struct RingBuffer {
 impl RingBuffer {
     pub fn new(cap: i16) -> Self {
         RingBuffer {
-            cap: cap,
+            cap,
             len: 0,
             head: 0,
             tail: -1,
@@ -43,6 +43,9 @@ impl RingBuffer {
         for e in self.data.iter_mut() {
             *e = 0;
         }
+        self.len = 0;
+        self.head = 0;
+        self.tail = -1;
     }
 
     pub fn size(&self) -> i16 {
@@ -103,5 +106,6 @@ mod tests {
         assert_eq!(rb.data[1], 0);
         assert_eq!(rb.data[2], 0);
         assert_eq!(rb.data[3], 0);
+        assert_eq!(rb.size(), 0);
     }
 }